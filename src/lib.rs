@@ -1,540 +1,6417 @@
+// `is_multiple_of` is only stable since Rust 1.87, so the modulo is kept for older toolchains
+#![allow(clippy::manual_is_multiple_of)]
+
 use std::{
-    fs,
+    cell::Cell,
+    error::Error,
+    fmt, fs, io,
     marker::PhantomData,
-    ops::{Index, IndexMut},
-    os::unix::prelude::AsRawFd,
-    slice::{Iter, IterMut},
+    ops::{BitOr, BitOrAssign, Index, IndexMut, Range, RangeInclusive, RangeToInclusive},
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::{
+            fs::FileExt,
+            prelude::{AsRawFd, RawFd},
+        },
+    },
+    path::Path,
+    slice::{ChunksExact, Iter, IterMut},
 };
 
-pub use mmap::MapOption;
+pub use mmap::{MapError, MapOption};
 use mmap::MemoryMap;
+#[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
 /// The main abstraction over the `mmap` crate.
 /// Owns a memory map and provides simplified and safe access to this memory region.
 /// Also provides some additional features such as iterators over the data.
 pub struct EasyMmap<'a, T> {
-    _map: MemoryMap,
+    /// `None` for views over memory owned elsewhere, see `from_raw`.
+    _map: Option<MemoryMap>,
     _data: &'a mut [T],
     capacity: usize,
     _file: Option<fs::File>,
+    file_offset: u64,
+    _options: Vec<MapOption>,
+    flags: MapFlags,
+    trim_on_drop: bool,
+    dirty: Cell<bool>,
+    checksum: Cell<Option<u64>>,
+    checksum_pending: Cell<Option<usize>>,
+    sync_on_write: bool,
+    sync_pending: Cell<Option<usize>>,
+    cursor: usize,
+    huge_pages: bool,
 }
 
 impl<'a, T> EasyMmap<'a, T>
 where
     T: Copy,
 {
-    /// Creates a new EasyMmap struct with enough capacity to hold `capacity` elements of type `T`.
-    fn new(capacity: usize, options: &[MapOption], file: Option<fs::File>) -> EasyMmap<'a, T> {
-        let map = MemoryMap::new(capacity * std::mem::size_of::<T>(), options).unwrap();
-        let slice = unsafe { std::slice::from_raw_parts_mut(map.data().cast::<T>(), capacity) };
+    /// Creates a new EasyMmap struct with enough capacity to hold `capacity` elements of type `T`,
+    /// starting `view_offset` bytes into the mapped region.
+    fn new_view(
+        view_offset: usize,
+        capacity: usize,
+        options: &[MapOption],
+        file: Option<fs::File>,
+    ) -> Result<EasyMmap<'a, T>, MapError> {
+        let (map, slice) = Self::new_region(view_offset, capacity, options)?;
 
-        EasyMmap {
-            _map: map,
+        Ok(EasyMmap {
+            _map: Some(map),
             _data: slice,
             capacity,
             _file: file,
+            file_offset: 0,
+            _options: options.to_vec(),
+            flags: MapFlags::from_options(options),
+            trim_on_drop: false,
+            dirty: Cell::new(false),
+            checksum: Cell::new(None),
+            checksum_pending: Cell::new(None),
+            sync_on_write: false,
+            sync_pending: Cell::new(None),
+            cursor: 0,
+            huge_pages: false,
+        })
+    }
+
+    /// Maps a region able to hold `capacity` elements after `view_offset` bytes,
+    /// returning it along with a slice over the elements.
+    fn new_region(
+        view_offset: usize,
+        capacity: usize,
+        options: &[MapOption],
+    ) -> Result<(MemoryMap, &'a mut [T]), MapError> {
+        assert!(
+            std::mem::size_of::<T>() != 0,
+            "Zero-sized types are not supported"
+        );
+        let mut len = view_offset + capacity * std::mem::size_of::<T>();
+        // Huge page mappings are unmapped and protected in whole huge pages, so they must span whole huge pages
+        if uses_huge_pages(options) {
+            let huge_page_size = huge_page_size();
+            len = len.div_ceil(huge_page_size) * huge_page_size;
+        }
+        // An empty region cannot be mapped, so maps without elements reserve a page they never touch
+        let map = MemoryMap::new(len.max(1), options)?;
+        let slice = unsafe {
+            std::slice::from_raw_parts_mut(map.data().add(view_offset).cast::<T>(), capacity)
+        };
+        Ok((map, slice))
+    }
+
+    /// Creates a read-write view over `capacity` elements starting at `ptr`, e.g. a region mapped by another library.
+    /// The view does not own the memory: nothing is unmapped or freed when it is dropped, and it cannot be resized.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must be non-null, aligned for `T`, and valid for reads and writes of `capacity` elements,
+    ///   which must all be initialized.
+    /// - The memory must stay valid until the view is dropped, and must not be freed or unmapped before that.
+    /// - While the view is alive, the memory must not be accessed through any other pointer or reference.
+    pub unsafe fn from_raw(ptr: *mut T, capacity: usize) -> EasyMmap<'static, T> {
+        EasyMmap {
+            _map: None,
+            _data: unsafe { std::slice::from_raw_parts_mut(ptr, capacity) },
+            capacity,
+            _file: None,
+            file_offset: 0,
+            _options: Vec::new(),
+            flags: MapFlags::READABLE | MapFlags::WRITABLE,
+            trim_on_drop: false,
+            dirty: Cell::new(false),
+            checksum: Cell::new(None),
+            checksum_pending: Cell::new(None),
+            sync_on_write: false,
+            sync_pending: Cell::new(None),
+            cursor: 0,
+            huge_pages: false,
+        }
+    }
+
+    /// Panics if the map was not built writable, instead of letting a write fault on a read-only region.
+    fn assert_writable(&self) {
+        if !self.flags.contains(MapFlags::WRITABLE) {
+            panic!("The map is not writable");
+        }
+    }
+
+    /// Marks the map as modified. The running checksum is forgotten, as the modified elements are unknown.
+    fn mark_dirty(&self) {
+        self.dirty.set(true);
+        self.forget_checksum();
+    }
+
+    /// Resets the running checksum to unknown, so that it is computed again from the whole map.
+    fn forget_checksum(&self) {
+        self.checksum.set(None);
+        self.checksum_pending.set(None);
+    }
+
+    /// Adds the element handed out by the last `index_mut` back into the running checksum.
+    fn resolve_checksum(&self) {
+        if let (Some(sum), Some(index)) = (self.checksum.get(), self.checksum_pending.take()) {
+            self.checksum
+                .set(Some(sum ^ element_hash(index, &self._data[index])));
+        }
+    }
+
+    /// Schedules the write back of the element handed out by the last `index_mut`, see `sync_on_write`.
+    fn sync_pending_write(&self) {
+        if let Some(index) = self.sync_pending.take() {
+            let _ = self.sync_range(index..index + 1, libc::MS_ASYNC);
+        }
+    }
+
+    /// Synchronizes the pages holding the elements in `range` with the file.
+    fn sync_range(&self, range: Range<usize>, flags: libc::c_int) -> io::Result<()> {
+        if self._map.is_none() {
+            return Ok(());
+        }
+
+        let (addr, len) = self.page_span(range);
+        if unsafe { libc::msync(addr, len, flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Combined hash of the elements in `start..end`, see `running_checksum`.
+    fn range_hash(&self, start: usize, end: usize) -> u64 {
+        (start..end).fold(0, |sum, i| sum ^ element_hash(i, &self._data[i]))
+    }
+
+    /// Panics if `count` elements starting at `offset` do not fit in the memory map.
+    fn assert_range(&self, offset: usize, count: usize) {
+        let in_bounds = matches!(offset.checked_add(count), Some(end) if end <= self.len());
+        if !in_bounds {
+            panic!(
+                "Range {}..{} is out of bounds for type {}",
+                offset,
+                offset.saturating_add(count),
+                std::any::type_name::<T>(),
+            );
+        }
+    }
+
+    /// Returns the backing file, or an error for anonymous maps.
+    fn backing_file(&self) -> io::Result<&fs::File> {
+        self._file.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The map is not backed by a file",
+            )
+        })
+    }
+
+    /// Converts the inclusive range `start..=end` to an exclusive one, panicking if it is out of bounds.
+    fn inclusive_range(&self, start: usize, end: usize) -> Range<usize> {
+        if end >= self.len() || start > end + 1 {
+            panic!(
+                "Range {}..={} is out of bounds for type {}",
+                start,
+                end,
+                std::any::type_name::<T>(),
+            );
         }
+        start..end + 1
     }
 
     /// How many elements can be stored in the memory map.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.capacity
     }
 
+    /// The size in bytes of the elements stored in the memory map.
+    pub fn len_bytes(&self) -> usize {
+        self.capacity * std::mem::size_of::<T>()
+    }
+
+    /// Whether the memory map is backed by a file.
+    pub fn is_file_backed(&self) -> bool {
+        self._file.is_some()
+    }
+
+    /// Whether the backing file still ends exactly where the map does, e.g. to detect a file that was
+    /// truncated or only partially written by another process. Always `true` for anonymous maps.
+    pub fn validate_against_file(&self) -> io::Result<bool> {
+        match &self._file {
+            Some(file) => Ok(file.metadata()?.len() == self.file_offset + self.len_bytes() as u64),
+            None => Ok(true),
+        }
+    }
+
+    /// Whether the map is backed by huge pages, see `EasyMmapBuilder::huge_pages_or_fallback`.
+    pub fn uses_huge_pages(&self) -> bool {
+        self.huge_pages
+    }
+
+    /// The options the memory map was built with.
+    pub fn options(&self) -> MapFlags {
+        self.flags
+    }
+
+    /// Returns a raw pointer to the start of the memory map data.
+    pub fn as_ptr(&self) -> *const T {
+        self._data.as_ptr()
+    }
+
+    /// Returns a reference to the element at `index`, or an `OutOfBounds` error if there is none.
+    pub fn try_index(&self, index: usize) -> Result<&T, OutOfBounds> {
+        self._data.get(index).ok_or(OutOfBounds {
+            index,
+            len: self.len(),
+        })
+    }
+
+    /// Returns a mutable reference to the element at `index`, panicking if it is out of bounds.
+    pub fn entry(&mut self, index: usize) -> &mut T {
+        &mut self[index]
+    }
+
+    /// Returns a mutable reference to the element at `index`, first setting it to `default` if it still holds
+    /// `T::default()`. Useful for sparse tables, where unused slots are zero-initialized.
+    pub fn entry_or_insert(&mut self, index: usize, default: T) -> &mut T
+    where
+        T: PartialEq + Default,
+    {
+        let entry = self.entry(index);
+        if *entry == T::default() {
+            *entry = default;
+        }
+        entry
+    }
+
+    /// Stores `value` at `index` and returns the element it replaced.
+    /// With `sync_on_write`, the element is scheduled for write back before this returns.
+    pub fn replace(&mut self, index: usize, value: T) -> T {
+        let old = std::mem::replace(&mut self[index], value);
+        self.sync_pending_write();
+        old
+    }
+
+    /// Hints the CPU to bring the cache line holding the element at `index` into the cache.
+    /// Only has an effect on x86_64, it is a no-op on other targets.
+    pub fn prefetch_cacheline(&self, index: usize) {
+        let _ptr = &self[index] as *const T;
+
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            _mm_prefetch::<_MM_HINT_T0>(_ptr.cast::<i8>());
+        }
+    }
+
     /// Returns a read-only iterator over the elements of the memory map.
     pub fn iter(&self) -> Iter<'_, T> {
         self._data.iter()
     }
 
+    /// Returns an iterator over every `step`-th element of the memory map, starting at the first one.
+    /// Useful for column-wise access over row-major data.
+    pub fn step_by_iter(&self, step: usize) -> impl Iterator<Item = &T> {
+        self._data.iter().step_by(step)
+    }
+
+    /// Returns an iterator over the chunks of exactly `size` elements, e.g. fixed-width records.
+    /// The trailing elements that don't fill a chunk are left out, and are returned by its `remainder`.
+    pub fn chunks_exact(&self, size: usize) -> ChunksExact<'_, T> {
+        self._data.chunks_exact(size)
+    }
+
+    /// Returns an iterator over the elements of the memory map paired with their byte offset in the mapping.
+    pub fn iter_byte_offsets(&self) -> impl Iterator<Item = (usize, &T)> {
+        self._data
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i * std::mem::size_of::<T>(), v))
+    }
+
     /// Returns a mutable iterator over the elements of the memory map.
     pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        self.assert_writable();
+        self.mark_dirty();
         self._data.iter_mut()
     }
 
     /// Returns a parallel iterator over the elements of the memory map.
+    #[cfg(feature = "rayon")]
     pub fn par_iter(&self) -> impl ParallelIterator<Item = &T> where T: Send + Sync {
         self._data.par_iter()
     }
 
     /// Returns a mutable parallel iterator over the elements of the memory map.
+    #[cfg(feature = "rayon")]
     pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut T> where T : Send + Sync{
+        self.assert_writable();
+        self.mark_dirty();
         self._data.par_iter_mut()
     }
 
-    /// Returns a read-only slice of the memory map data.
-    pub fn get_data_as_slice(&self) -> &[T] {
-        self._data
+    /// Same as `par_iter`, but rayon never splits the work in pieces smaller than `min_len` elements.
+    /// Useful to keep small maps from being over-subdivided.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter_min_len(&self, min_len: usize) -> impl ParallelIterator<Item = &T>
+    where
+        T: Send + Sync,
+    {
+        self._data.par_iter().with_min_len(min_len)
     }
 
-    /// Returns a mutable slice of the memory map data.
-    pub fn get_data_as_slice_mut(&mut self) -> &mut [T] {
+    /// Calls `f` in parallel with the index and a mutable reference of every element, the parallel
+    /// counterpart of `iter_mut().enumerate().for_each(...)`.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each_indexed(&mut self, f: impl Fn(usize, &mut T) + Sync)
+    where
+        T: Send + Sync,
+    {
+        self.assert_writable();
+        self.mark_dirty();
         self._data
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, v)| f(i, v));
     }
 
-    /// Convenience method for filling the memory map with a custom function
-    /// Example:
-    /// ```
-    /// let mut mmap = easy_mmap::EasyMmapBuilder::new()
-    ///                            .readable()
-    ///                            .writable()
-    ///                            .capacity(5)
-    ///                            .build();
-    ///
-    /// mmap.fill(|i| i as u32);
-    /// assert_eq!(mmap.get_data_as_slice(), &[0, 1, 2, 3, 4]);
-    /// ```
-    pub fn fill(&mut self, f: impl Fn(usize) -> T) {
-        for (i, v) in self._data.iter_mut().enumerate() {
-            *v = f(i);
-        }
+    /// Searches the elements in parallel for one satisfying `pred`, stopping as soon as one is found.
+    /// If several elements match, any of them may be returned.
+    #[cfg(feature = "rayon")]
+    pub fn par_find_any<P: Fn(&T) -> bool + Sync>(&self, pred: P) -> Option<&T>
+    where
+        T: Send + Sync,
+    {
+        self._data.par_iter().find_any(|v| pred(v))
     }
-}
-
-/// The structure can be indexed similarly to an array.
-/// Example:
-/// ```
-/// let mut mmap = easy_mmap::EasyMmapBuilder::new()
-///                     .options(&[
-///                         mmap::MapOption::MapWritable,
-///                         mmap::MapOption::MapReadable,
-///                     ])
-///                     .capacity(10)
-///                     .build();
-/// mmap[0] = 1;
-/// println!("{}", mmap[0]);
-/// ```
-impl<'a, T> Index<usize> for EasyMmap<'a, T>
-where
-    T: Copy,
-{
-    type Output = T;
 
-    fn index(&self, index: usize) -> &Self::Output {
-        if index >= self.len() {
-            panic!(
-                "Index {} is out of bounds for type {}",
-                index,
-                std::any::type_name::<T>(),
-            );
-        };
-        &self._data[index]
+    /// Runs `f` in parallel over chunks of `chunk` elements.
+    /// Each worker gets its own scratch state created by `init`, which is reused across the chunks it processes.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each_chunk<S, I, F>(&self, chunk: usize, init: I, f: F)
+    where
+        T: Send + Sync,
+        I: Fn() -> S + Sync,
+        F: Fn(&mut S, &[T]) + Sync,
+    {
+        self._data
+            .par_chunks(chunk)
+            .for_each_init(&init, |state, c| f(state, c));
     }
-}
 
-/// The structure can be indexed an array or slice.
-/// See the `Index` trait for an example.
-impl<'a, T> IndexMut<usize> for EasyMmap<'a, T>
-where
-    T: Copy,
-{
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        if index >= self.len() {
-            panic!(
-                "Index {} is out of bounds for type {}",
-                index,
-                std::any::type_name::<T>(),
+    /// Counts in parallel how many elements fall in each of `buckets` buckets, as chosen by `bucket_of`.
+    /// Panics if `bucket_of` returns a bucket past the last one.
+    #[cfg(feature = "rayon")]
+    pub fn par_histogram(&self, buckets: usize, bucket_of: impl Fn(&T) -> usize + Sync) -> Vec<u64>
+    where
+        T: Send + Sync,
+    {
+        self._data
+            .par_iter()
+            .fold(
+                || vec![0u64; buckets],
+                |mut counts, v| {
+                    counts[bucket_of(v)] += 1;
+                    counts
+                },
+            )
+            .reduce(
+                || vec![0u64; buckets],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(x, y)| *x += y);
+                    a
+                },
             )
-        }
-        &mut self._data[index]
     }
-}
 
-/// The builder class for the EasyMmap struct.
-/// Provides an easy-to-use interface to create a new EasyMmap struct.
-pub struct EasyMmapBuilder<T> {
-    file: Option<fs::File>,
-    capacity: usize,
-    options: Vec<MapOption>,
-    _type: PhantomData<T>,
-}
+    /// Counts in a single parallel pass how many elements satisfy each of the predicates in `preds`.
+    #[cfg(feature = "rayon")]
+    pub fn par_tally<const N: usize>(&self, preds: [&(dyn Fn(&T) -> bool + Sync); N]) -> [u64; N]
+    where
+        T: Send + Sync,
+    {
+        self._data
+            .par_iter()
+            .fold(
+                || [0u64; N],
+                |mut counts, v| {
+                    for (count, pred) in counts.iter_mut().zip(&preds) {
+                        *count += pred(v) as u64;
+                    }
+                    counts
+                },
+            )
+            .reduce(
+                || [0u64; N],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(x, y)| *x += y);
+                    a
+                },
+            )
+    }
 
-impl<'a, T> EasyMmapBuilder<T> {
-    /// Creates a new EasyMmapBuilder struct.
-    pub fn new() -> EasyMmapBuilder<T> {
-        EasyMmapBuilder {
-            file: None,
-            capacity: 0,
-            options: Vec::new(),
-            _type: PhantomData,
-        }
+    /// Returns the smallest and largest elements in a single parallel pass, or `None` if the map is empty.
+    #[cfg(feature = "rayon")]
+    pub fn par_min_max(&self) -> Option<(T, T)>
+    where
+        T: Ord + Send + Sync,
+    {
+        self._data
+            .par_iter()
+            .map(|&v| (v, v))
+            .reduce_with(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)))
     }
 
-    /// Builds the memory map with the given specifications.
-    /// If the file has been specified, its size will be set to the requirements of the map.
-    pub fn build(mut self) -> EasyMmap<'a, T>
+    /// Estimates the number of distinct elements with a HyperLogLog sketch of 4096 registers, built in
+    /// parallel. The standard error is about 1.6%, and small counts are estimated with linear counting.
+    #[cfg(feature = "hll")]
+    pub fn approx_cardinality(&self) -> u64
     where
-        T: Copy,
+        T: std::hash::Hash + Send + Sync,
     {
-        if self.file.is_some() {
-            let file = self.file.unwrap();
-            // allocate enough size in the file
-            file.set_len((self.capacity * std::mem::size_of::<T>()) as u64)
-                .unwrap();
+        use std::hash::{DefaultHasher, Hasher};
 
-            // Get file descriptor of file
-            self.options.push(MapOption::MapFd(file.as_raw_fd()));
-            self.options // To make the code share the file in memory
-                .push(MapOption::MapNonStandardFlags(libc::MAP_SHARED));
+        const P: u32 = 12;
+        const M: usize = 1 << P;
+        let registers = self
+            ._data
+            .par_iter()
+            .fold(
+                || vec![0u8; M],
+                |mut registers, v| {
+                    let mut hasher = DefaultHasher::new();
+                    v.hash(&mut hasher);
+                    let hash = hasher.finish();
+                    // The top bits select the register, the rank is the position of the first set bit after them
+                    let index = (hash >> (64 - P)) as usize;
+                    let rank = ((hash << P) | (1 << (P - 1))).leading_zeros() as u8 + 1;
+                    registers[index] = registers[index].max(rank);
+                    registers
+                },
+            )
+            .reduce(
+                || vec![0u8; M],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(x, y)| *x = (*x).max(y));
+                    a
+                },
+            );
 
-            self.file = Some(file);
+        let m = M as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = registers
+            .iter()
+            .map(|&r| 2f64.powi(-(r as i32)))
+            .sum::<f64>();
+        let estimate = alpha * m * m / sum;
+        let zeros = registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
         }
+    }
 
-        EasyMmap::new(self.capacity, &self.options, self.file)
+    /// Creates a new anonymous map where each element is computed in parallel by `f` from the window of
+    /// `radius` elements on both sides of it, e.g. for stencils or cellular automata.
+    /// Windows are clamped at the edges, so they are shorter there.
+    #[cfg(feature = "rayon")]
+    pub fn map_with_neighbors(&self, radius: usize, f: impl Fn(&[T]) -> T + Sync) -> EasyMmap<'a, T>
+    where
+        T: Send + Sync,
+    {
+        let map = EasyMmapBuilder::new()
+            .capacity(self.len())
+            .readable()
+            .writable()
+            .build();
+        let data = &*self._data;
+        map._data.par_iter_mut().enumerate().for_each(|(i, v)| {
+            let window = i.saturating_sub(radius)..(i + radius + 1).min(data.len());
+            *v = f(&data[window]);
+        });
+        map
     }
 
-    /// Passes the ownership of the file to the memory map.
-    pub fn file(mut self, file: fs::File) -> EasyMmapBuilder<T> {
-        self.file = Some(file);
-        self
+    /// Writes each `(index, value)` pair of `records` into the map in parallel, e.g. records produced out of order.
+    /// Panics if an index is out of bounds or appears more than once.
+    #[cfg(feature = "rayon")]
+    pub fn par_write_records<I>(&mut self, records: I)
+    where
+        I: IndexedParallelIterator<Item = (usize, T)>,
+        T: Send + Sync,
+    {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        self.assert_writable();
+        self.mark_dirty();
+        let len = self.len();
+        // One bit per element claims each index, so no element is ever written by two threads at once
+        let claimed = (0..len.div_ceil(64))
+            .map(|_| AtomicU64::new(0))
+            .collect::<Vec<_>>();
+        let base = self._data.as_mut_ptr() as usize;
+        records.for_each(|(index, value)| {
+            assert!(
+                index < len,
+                "Index {} out of bounds for length {}",
+                index,
+                len
+            );
+            let bit = 1 << (index % 64);
+            assert!(
+                claimed[index / 64].fetch_or(bit, Ordering::Relaxed) & bit == 0,
+                "Index {} is written more than once",
+                index
+            );
+            unsafe { (base as *mut T).add(index).write(value) };
+        });
     }
 
-    /// Sets the capacity that the mapped region must have.
-    /// This capacity must be the number of objects of type `T` that can be stored in the memory map.
-    pub fn capacity(mut self, capacity: usize) -> EasyMmapBuilder<T> {
-        self.capacity = capacity;
-        self
+    /// Sorts the elements in parallel by the key extracted with `f`, e.g. a field of a record.
+    /// The sort is stable.
+    #[cfg(feature = "rayon")]
+    pub fn par_sort_by_key<K: Ord + Send, F: Fn(&T) -> K + Sync>(&mut self, f: F)
+    where
+        T: Send,
+    {
+        self.assert_writable();
+        self.mark_dirty();
+        self._data.par_sort_by_key(f);
     }
 
-    /// Batch sets the options that the mapped region must have.
-    pub fn options(mut self, options: &[MapOption]) -> EasyMmapBuilder<T> {
-        self.options = options.to_vec();
-        self
+    /// Returns a read-only slice of the memory map data.
+    pub fn get_data_as_slice(&self) -> &[T] {
+        self._data
     }
 
-    /// Adds an individual option.
-    pub fn add_option(mut self, option: MapOption) -> EasyMmapBuilder<T> {
-        self.options.push(option);
-        self
+    /// Returns a mutable slice of the memory map data.
+    pub fn get_data_as_slice_mut(&mut self) -> &mut [T] {
+        self.assert_writable();
+        self.mark_dirty();
+        self._data
     }
 
-    pub fn readable(mut self) -> EasyMmapBuilder<T> {
-        self.options.push(MapOption::MapReadable);
-        self
+    /// Returns the memory map data as raw bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self._data.as_ptr().cast::<u8>(), self.len_bytes()) }
     }
 
-    pub fn writable(mut self) -> EasyMmapBuilder<T> {
-        self.options.push(MapOption::MapWritable);
-        self
+    /// Returns the memory map data as mutable raw bytes, e.g. to fill it from a byte source.
+    /// As any bytes can be written, `T` must be `FileSafe`, like for the bytes read back from a file.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8]
+    where
+        T: FileSafe,
+    {
+        self.assert_writable();
+        self.mark_dirty();
+        unsafe {
+            std::slice::from_raw_parts_mut(self._data.as_mut_ptr().cast::<u8>(), self.len_bytes())
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Splits the elements into an unaligned prefix, a middle run of the wider integer type `U` and an
+    /// unaligned suffix, e.g. to process the middle with aligned SIMD loads.
+    /// Both types are integers, for which any bit pattern is valid, so the contract of `slice::align_to` always holds.
+    pub fn align_to_simd<U>(&self) -> (&[T], &[U], &[T])
+    where
+        T: Integer,
+        U: Integer,
+    {
+        unsafe { self._data.align_to::<U>() }
+    }
 
-    fn create_random_file() -> fs::File {
-        fs::OpenOptions::new()
-            .create(true)
-            .read(true)
+    /// Reads the bytes of `reader` straight into the map until it is full or the reader ends, e.g. to initialize
+    /// a large file-backed map from a stream without buffering it. Returns the number of whole elements filled;
+    /// the bytes of a trailing partial element are still written.
+    pub fn fill_from_reader<R: io::Read>(&mut self, mut reader: R) -> io::Result<usize>
+    where
+        T: FileSafe,
+    {
+        let bytes = self.as_bytes_mut();
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match reader.read(&mut bytes[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled / std::mem::size_of::<T>())
+    }
+
+    /// Returns a reader over the raw bytes of the memory map, e.g. to `io::copy` it into a file or socket.
+    pub fn reader(&self) -> impl io::Read + '_ {
+        io::Cursor::new(self.as_bytes())
+    }
+
+    /// Returns a writer over the raw bytes of the memory map, starting at the first byte.
+    /// Writes past the end of the map fail with `WriteZero`. As any bytes can be written, `T` must be `FileSafe`.
+    pub fn writer(&mut self) -> impl io::Write + '_
+    where
+        T: FileSafe,
+    {
+        io::Cursor::new(self.as_bytes_mut())
+    }
+
+    /// Writes a snapshot of the memory map data to the file at `path`, creating or truncating it.
+    /// Unlike a file-backed map, later changes to the map are not reflected in the file.
+    pub fn copy_to_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.as_bytes())
+    }
+
+    /// Saves the elements to a new file at `path` and returns a map over it, so later writes go to the file,
+    /// e.g. to keep a map built in memory.
+    pub fn persist_as(self, path: impl AsRef<Path>) -> io::Result<EasyMmap<'a, T>>
+    where
+        T: FileSafe,
+    {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        let mut map = EasyMmapBuilder::new()
+            .capacity(self.len())
+            .readable()
+            .writable()
+            .file(file)
+            .try_build()
+            .map_err(io::Error::other)?;
+        map.write_at(0, self._data);
+        Ok(map)
+    }
+
+    /// Copies `buf.len()` elements starting at element `offset` into `buf`.
+    pub fn read_at(&self, offset: usize, buf: &mut [T]) {
+        self.assert_range(offset, buf.len());
+        buf.copy_from_slice(&self._data[offset..offset + buf.len()]);
+    }
+
+    /// Copies the elements of `src` into the memory map, starting at element `offset`.
+    pub fn write_at(&mut self, offset: usize, src: &[T]) {
+        self.assert_range(offset, src.len());
+        self.assert_writable();
+        self.dirty.set(true);
+        self.resolve_checksum();
+        let old = self
+            .checksum
+            .get()
+            .map(|sum| self.range_hash(offset, offset + src.len()) ^ sum);
+        self._data[offset..offset + src.len()].copy_from_slice(src);
+        if let Some(sum) = old {
+            self.checksum
+                .set(Some(sum ^ self.range_hash(offset, offset + src.len())));
+        }
+        if self.sync_on_write {
+            self.sync_pending_write();
+            let _ = self.sync_range(offset..offset + src.len(), libc::MS_ASYNC);
+        }
+    }
+
+    /// Returns a view of the elements as atomics, e.g. to update a map shared between processes.
+    /// Borrows the map mutably, so no plain reference to the elements is alive while they are written through
+    /// the atomics.
+    pub fn as_atomic(&mut self) -> &[T::Atomic]
+    where
+        T: AtomicInteger,
+    {
+        self.assert_writable();
+        self.mark_dirty();
+        let ptr = self._data.as_mut_ptr().cast::<T::Atomic>();
+        assert!(
+            ptr.is_aligned(),
+            "The map data is not aligned for {}",
+            std::any::type_name::<T::Atomic>()
+        );
+        // The atomic types have the same size as their integer
+        unsafe { std::slice::from_raw_parts(ptr, self.len()) }
+    }
+
+    /// Atomically replaces the element at `index` with `new` if it equals `current`.
+    /// Returns the previous value, as `Ok` if it was replaced and as `Err` otherwise.
+    /// Borrows the map mutably, as the element must not be written behind a live reference to it.
+    pub fn compare_exchange(&mut self, index: usize, current: T, new: T) -> Result<T, T>
+    where
+        T: AtomicInteger,
+    {
+        self.assert_range(index, 1);
+        T::compare_exchange(&self.as_atomic()[index], current, new)
+    }
+
+    /// Issues a release fence, so the plain writes made to the map before it are visible to any thread or
+    /// process that observes a later atomic write, e.g. a ready flag set with `as_atomic`, and then calls
+    /// `read_barrier`.
+    pub fn write_barrier(&self) {
+        std::sync::atomic::fence(std::sync::atomic::Ordering::Release);
+    }
+
+    /// Issues an acquire fence, pairing with a `write_barrier` of the producer: after observing its atomic
+    /// signal, the plain writes it made before the barrier are visible.
+    pub fn read_barrier(&self) {
+        std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire);
+    }
+
+    /// Returns the elements in `range`, or `None` if the range is out of bounds.
+    pub fn get_slice(&self, range: Range<usize>) -> Option<&[T]> {
+        self._data.get(range)
+    }
+
+    /// Returns the `N` elements starting at `start` as an array, e.g. a fixed-width record, or `None` if they
+    /// are out of bounds.
+    pub fn array_ref<const N: usize>(&self, start: usize) -> Option<&[T; N]> {
+        self._data
+            .get(start..start.checked_add(N)?)?
+            .try_into()
+            .ok()
+    }
+
+    /// Returns the elements in `range` mutably, or `None` if the range is out of bounds.
+    pub fn get_slice_mut(&mut self, range: Range<usize>) -> Option<&mut [T]> {
+        self.assert_writable();
+        self.mark_dirty();
+        self._data.get_mut(range)
+    }
+
+    /// Returns the absolute byte offset in the backing file of the element at `index`, e.g. to build an
+    /// on-disk index. Returns `None` if the index is out of bounds or the map is anonymous.
+    pub fn file_offset_of(&self, index: usize) -> Option<u64> {
+        if index >= self.len() || self._file.is_none() {
+            return None;
+        }
+        Some(self.file_offset + (index * std::mem::size_of::<T>()) as u64)
+    }
+
+    /// Returns the element starting at byte `offset`, e.g. an offset read from an index into the file.
+    /// The offset must be a multiple of the size of `T`.
+    pub fn at_byte_offset(&self, offset: usize) -> &T {
+        let size = std::mem::size_of::<T>();
+        assert!(
+            offset % size == 0,
+            "Byte offset {} is not a multiple of the size of {}",
+            offset,
+            std::any::type_name::<T>()
+        );
+        assert!(
+            offset < self.len_bytes(),
+            "Byte offset {} is out of bounds for a map of {} bytes",
+            offset,
+            self.len_bytes()
+        );
+        &self._data[offset / size]
+    }
+
+    /// Returns mutable references to the elements at each of `indices`, or `None` if any of them is out of bounds
+    /// or appears more than once.
+    pub fn get_many_mut<const N: usize>(&mut self, indices: [usize; N]) -> Option<[&mut T; N]> {
+        self.assert_writable();
+        self.mark_dirty();
+        for (i, index) in indices.iter().enumerate() {
+            if *index >= self.len() || indices[..i].contains(index) {
+                return None;
+            }
+        }
+        // The indices are in bounds and distinct, so the references never alias
+        let data = self._data.as_mut_ptr();
+        Some(indices.map(|index| unsafe { &mut *data.add(index) }))
+    }
+
+    /// Returns the first element and the rest of the memory map, or `None` if it is empty.
+    pub fn split_first(&self) -> Option<(&T, &[T])> {
+        self._data.split_first()
+    }
+
+    /// Returns the last element and the rest of the memory map, or `None` if it is empty.
+    pub fn split_last(&self) -> Option<(&T, &[T])> {
+        self._data.split_last()
+    }
+
+    /// Convenience method for filling the memory map with a custom function
+    /// Example:
+    /// ```
+    /// let mut mmap = easy_mmap::EasyMmapBuilder::new()
+    ///                            .readable()
+    ///                            .writable()
+    ///                            .capacity(5)
+    ///                            .build();
+    ///
+    /// mmap.fill(|i| i as u32);
+    /// assert_eq!(mmap.get_data_as_slice(), &[0, 1, 2, 3, 4]);
+    /// ```
+    pub fn fill(&mut self, f: impl Fn(usize) -> T) {
+        self.assert_writable();
+        self.mark_dirty();
+        for (i, v) in self._data.iter_mut().enumerate() {
+            *v = f(i);
+        }
+    }
+
+    /// Same as `fill`, but returns an error instead of panicking if the map is not writable.
+    pub fn try_fill_checked(&mut self, f: impl Fn(usize) -> T) -> Result<(), EasyMmapError> {
+        if !self.flags.contains(MapFlags::WRITABLE) {
+            return Err(EasyMmapError::NotWritable);
+        }
+        self.fill(f);
+        Ok(())
+    }
+
+    /// Fills the map with `f`, then turns it read-only with `mprotect`, e.g. for lookup tables that are
+    /// initialized once. Any later write panics as for maps that were not built writable.
+    pub fn seal(mut self, f: impl Fn(usize) -> T) -> io::Result<Self> {
+        self.fill(f);
+        self.protect_read_only()?;
+        Ok(self)
+    }
+
+    /// Turns the mapped region read-only with `mprotect`, see `seal`.
+    fn protect_read_only(&mut self) -> io::Result<()> {
+        let Some(map) = &self._map else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Cannot protect a view over memory owned elsewhere",
+            ));
+        };
+        let result = unsafe {
+            libc::mprotect(
+                map.data().cast::<libc::c_void>(),
+                map.len(),
+                libc::PROT_READ,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Keep the map read-only if it is mapped again
+        self._options
+            .retain(|o| !matches!(o, MapOption::MapWritable));
+        self.flags = MapFlags::from_options(&self._options);
+        Ok(())
+    }
+
+    /// Same as `fill`, but the closure can fail.
+    /// Stops at the first error and returns it, leaving the elements written so far in place.
+    pub fn try_fill<E>(&mut self, f: impl Fn(usize) -> Result<T, E>) -> Result<(), E> {
+        self.assert_writable();
+        self.mark_dirty();
+        for (i, v) in self._data.iter_mut().enumerate() {
+            *v = f(i)?;
+        }
+        Ok(())
+    }
+
+    /// Fills the map with `start, start + 1, start + 2, ...`, e.g. to generate an ID column.
+    pub fn fill_sequential(&mut self, start: T)
+    where
+        T: std::ops::Add<Output = T> + From<u8>,
+    {
+        self.assert_writable();
+        self.mark_dirty();
+        let mut next = start;
+        for (i, v) in self._data.iter_mut().enumerate() {
+            // Only step between elements, so the last value doesn't overflow
+            if i > 0 {
+                next = next + T::from(1);
+            }
+            *v = next;
+        }
+    }
+
+    /// Fills the raw bytes of the map with a deterministic pseudo-random stream generated from `seed`.
+    /// As random bytes may not form a valid value of every type, e.g. `bool`, `T` must be `FileSafe`.
+    #[cfg(feature = "rand")]
+    pub fn fill_random_bytes(&mut self, seed: u64)
+    where
+        T: FileSafe,
+    {
+        use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+        StdRng::seed_from_u64(seed).fill_bytes(self.as_bytes_mut());
+    }
+
+    /// Fills the elements in `range` in parallel, calling `f` with the absolute index of each element.
+    /// Elements outside of the range are left untouched.
+    #[cfg(feature = "rayon")]
+    pub fn fill_range_parallel(&mut self, range: Range<usize>, f: impl Fn(usize) -> T + Sync)
+    where
+        T: Send + Sync,
+    {
+        self.assert_writable();
+        self.mark_dirty();
+        let start = range.start;
+        self._data[range]
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, v)| *v = f(start + i));
+    }
+
+    /// Fills the map in parallel chunks of `chunk` elements, calling `f` with the index of each element.
+    /// `on_chunk` is called with the index of every chunk once it is filled, e.g. to report progress.
+    #[cfg(feature = "rayon")]
+    pub fn fill_parallel_progress(
+        &mut self,
+        chunk: usize,
+        f: impl Fn(usize) -> T + Sync,
+        on_chunk: impl Fn(usize) + Sync,
+    ) where
+        T: Send + Sync,
+    {
+        self.assert_writable();
+        self.mark_dirty();
+        self._data
+            .par_chunks_mut(chunk)
+            .enumerate()
+            .for_each(|(c, values)| {
+                for (i, v) in values.iter_mut().enumerate() {
+                    *v = f(c * chunk + i);
+                }
+                on_chunk(c);
+            });
+    }
+
+    /// Fills a map holding row-major 2D data, e.g. an image or a matrix, in parallel.
+    /// `f` is called with the row and column of each element, rows being `width` elements long.
+    #[cfg(feature = "rayon")]
+    pub fn fill_2d(&mut self, width: usize, f: impl Fn(usize, usize) -> T + Sync)
+    where
+        T: Send + Sync,
+    {
+        assert!(
+            width != 0 && self.len() % width == 0,
+            "The length {} is not a multiple of the width {}",
+            self.len(),
+            width
+        );
+        self.assert_writable();
+        self.mark_dirty();
+        self._data
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(row, values)| {
+                for (col, v) in values.iter_mut().enumerate() {
+                    *v = f(row, col);
+                }
+            });
+    }
+
+    /// Fills a map holding interleaved multi-channel data, e.g. audio samples or sensor readings.
+    /// `f` is called with the frame index and channel of each element, frames being `channels` elements long.
+    pub fn fill_channels(&mut self, channels: usize, f: impl Fn(usize, usize) -> T) {
+        assert!(
+            channels != 0 && self.len() % channels == 0,
+            "The length {} is not a multiple of the channel count {}",
+            self.len(),
+            channels
+        );
+        self.assert_writable();
+        self.mark_dirty();
+        for (frame, values) in self._data.chunks_mut(channels).enumerate() {
+            for (channel, v) in values.iter_mut().enumerate() {
+                *v = f(frame, channel);
+            }
+        }
+    }
+
+    /// Changes the capacity of the memory map, keeping the elements that fit in the new capacity.
+    /// The region is mapped again, and file-backed maps resize their file accordingly.
+    /// Fails if `new_capacity` is zero, leaving the map as it was.
+    pub fn resize(&mut self, new_capacity: usize) -> io::Result<()> {
+        if self.file_offset != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Cannot resize a map over a region of a file",
+            ));
+        }
+        if self._map.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Cannot resize a view over memory owned elsewhere",
+            ));
+        }
+
+        if new_capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot resize a map to no elements",
+            ));
+        }
+
+        self.sync_pending_write();
+        // Map the new region with the same options, so it keeps being shared and backed by the same file.
+        // The file is only resized once that succeeded, so the old region never outlives its file on failure.
+        let (map, data) =
+            Self::new_region(0, new_capacity, &self._options).map_err(io::Error::other)?;
+        if let Some(file) = &self._file {
+            file.set_len((new_capacity * std::mem::size_of::<T>()) as u64)?;
+        }
+
+        // The contents of file-backed maps are already in the file
+        if self._file.is_none() {
+            let count = self.len().min(new_capacity);
+            data[..count].copy_from_slice(&self._data[..count]);
+        }
+
+        // Swap the data before the old map is unmapped
+        self._data = data;
+        self._map = Some(map);
+        self.capacity = new_capacity;
+        self.cursor = self.cursor.min(new_capacity);
+        self.forget_checksum();
+        Ok(())
+    }
+
+    /// Shrinks a file-backed map and its file to the first `logical_len` elements, e.g. after compacting
+    /// the elements to keep at the front. Unlike `truncate`, the stale tail is removed from the file.
+    /// Fails if `logical_len` is zero, as the map can't be left without elements.
+    pub fn compact_file(&mut self, logical_len: usize) -> io::Result<()> {
+        self.backing_file()?;
+        if logical_len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot compact a map to no elements",
+            ));
+        }
+        if logical_len > self.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Cannot compact {} elements to a longer length of {}",
+                    self.len(),
+                    logical_len
+                ),
+            ));
+        }
+
+        self.flush()?;
+        self.resize(logical_len)
+    }
+
+    /// Grows the map and its file to `new_capacity` elements, keeping the data and the position of `push`.
+    /// Has no effect if the map is already at least that long, see `resize` to shrink it.
+    pub fn grow_to(&mut self, new_capacity: usize) -> io::Result<()> {
+        if new_capacity <= self.len() {
+            return Ok(());
+        }
+        self.resize(new_capacity)
+    }
+
+    /// Grows a file-backed map and its file to `new_capacity` elements with `mremap`, which extends the region
+    /// where it is if there is room after it, and moves it elsewhere otherwise. Maps placed `at_address` are
+    /// never moved and fail instead. As a `MemoryMap` can't take over the extended region, the file is then
+    /// mapped again over it; the contents live in the page cache, so nothing is copied. Falls back to `resize`
+    /// for other maps, or if `mremap` is not supported. On failure, the map is left as it was.
+    /// Has no effect if the map is already at least that long.
+    pub fn grow_in_place(&mut self, new_capacity: usize) -> io::Result<()> {
+        if new_capacity <= self.len() {
+            return Ok(());
+        }
+        if self._file.is_none() || self._map.is_none() || self.file_offset != 0 {
+            return self.resize(new_capacity);
+        }
+
+        self.sync_pending_write();
+        let old_len = (self.len() * std::mem::size_of::<T>()) as u64;
+        let new_len = new_capacity * std::mem::size_of::<T>();
+        self.backing_file()?.set_len(new_len as u64)?;
+
+        let pinned = self
+            ._options
+            .iter()
+            .any(|o| matches!(o, MapOption::MapAddr(_)));
+        let (old_ptr, old_map_len) = {
+            let old = self._map.as_ref().unwrap();
+            (old.data().cast::<libc::c_void>(), old.len())
+        };
+        let flags = if pinned { 0 } else { libc::MREMAP_MAYMOVE };
+        let ptr = unsafe { libc::mremap(old_ptr, old_map_len, new_len, flags) };
+        if ptr == libc::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            if pinned {
+                self.backing_file()?.set_len(old_len)?;
+                return Err(err);
+            }
+            return self.resize(new_capacity);
+        }
+
+        // The extended region is mapped again at its address, which must be replaced rather than avoided
+        let mut options = self
+            ._options
+            .iter()
+            .filter(|o| !matches!(o, MapOption::MapAddr(_)))
+            .map(|o| match o {
+                MapOption::MapNonStandardFlags(f) => {
+                    MapOption::MapNonStandardFlags(f & !libc::MAP_FIXED_NOREPLACE)
+                }
+                o => *o,
+            })
+            .collect::<Vec<_>>();
+        options.push(MapOption::MapAddr(ptr.cast::<u8>()));
+        let map = match MemoryMap::new(new_len, &options) {
+            Ok(map) => map,
+            Err(e) => {
+                self.restore_mapping(ptr, new_len, old_ptr, old_map_len)?;
+                self.backing_file()?.set_len(old_len)?;
+                return Err(io::Error::other(e));
+            }
+        };
+
+        // The old range now belongs to the new map, so it must not be unmapped
+        std::mem::forget(self._map.replace(map));
+        let map = self._map.as_ref().unwrap();
+        self._data =
+            unsafe { std::slice::from_raw_parts_mut(map.data().cast::<T>(), new_capacity) };
+        self.capacity = new_capacity;
+        self.forget_checksum();
+        Ok(())
+    }
+
+    /// Moves a region extended by `grow_in_place` back to the range of the current `MemoryMap`, or, if that
+    /// fails, maps the file again in place of the current `MemoryMap`.
+    fn restore_mapping(
+        &mut self,
+        ptr: *mut libc::c_void,
+        len: usize,
+        old_ptr: *mut libc::c_void,
+        old_len: usize,
+    ) -> io::Result<()> {
+        // A region extended where it is only needs to shrink again
+        let restored = if ptr == old_ptr {
+            unsafe { libc::mremap(ptr, len, old_len, 0) }
+        } else {
+            unsafe {
+                libc::mremap(
+                    ptr,
+                    len,
+                    old_len,
+                    libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED,
+                    old_ptr,
+                )
+            }
+        };
+        if restored != libc::MAP_FAILED {
+            return Ok(());
+        }
+
+        unsafe { libc::munmap(ptr, len) };
+        let (map, data) =
+            Self::new_region(0, self.capacity, &self._options).map_err(io::Error::other)?;
+        // The old range is not mapped anymore, so it must not be unmapped again
+        std::mem::forget(self._map.replace(map));
+        self._data = data;
+        Ok(())
+    }
+
+    /// Grows the map by `additional` elements, see `grow_to`.
+    pub fn grow_by(&mut self, additional: usize) -> io::Result<()> {
+        self.grow_to(self.len() + additional)
+    }
+
+    /// Writes `value` at the position of the append cursor and advances it, doubling the capacity when it is full.
+    /// The cursor starts at the first element. Only fails if the map cannot grow.
+    pub fn push(&mut self, value: T) -> io::Result<()> {
+        if self.cursor == self.len() {
+            self.grow_to((self.len() * 2).max(1))?;
+        }
+        let index = self.cursor;
+        self[index] = value;
+        self.cursor += 1;
+        Ok(())
+    }
+
+    /// The number of elements appended with `push`, i.e. the position of the append cursor.
+    pub fn pushed_len(&self) -> usize {
+        self.cursor
+    }
+
+    /// Resets the map for reuse, e.g. from a pool: the elements are zeroed, the append cursor goes back to the
+    /// first element and the map is no longer dirty. The mapping and its file are kept as is.
+    /// As zero is not a valid value of every type, e.g. references, `T` must be `FileSafe`.
+    pub fn recycle(&mut self)
+    where
+        T: FileSafe,
+    {
+        self.assert_writable();
+        self.fill_bytes(0);
+        self.cursor = 0;
+        self.dirty.set(false);
+    }
+
+    /// Same as `resize`, but returns an error instead of dropping elements when shrinking unless `allow_shrink` is set.
+    pub fn try_resize(&mut self, new_capacity: usize, allow_shrink: bool) -> io::Result<()> {
+        if new_capacity < self.len() && !allow_shrink {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Resizing from {} to {} elements would drop data",
+                    self.len(),
+                    new_capacity
+                ),
+            ));
+        }
+
+        self.resize(new_capacity)
+    }
+
+    /// Writes the changes made to a file-backed map to the file, waiting for the write to complete.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(map) = &self._map {
+            msync(map, libc::MS_SYNC)?;
+        }
+        self.dirty.set(false);
+        self.sync_pending.set(None);
+        Ok(())
+    }
+
+    /// Runs `f` on the map and then flushes it, so that the changes made by `f` are durable once this returns.
+    pub fn with_flush<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> io::Result<R> {
+        let result = f(self);
+        self.flush()?;
+        Ok(result)
+    }
+
+    /// Writes the changes made to the elements in `range` to the file, waiting for the write to complete.
+    /// Only the pages holding these elements are synchronized, unlike `flush`.
+    pub fn flush_range(&self, range: Range<usize>) -> io::Result<()> {
+        self.sync_range(range, libc::MS_SYNC)
+    }
+
+    /// Flushes the map and unmaps it, returning any error from the flush.
+    /// Dropping the map also flushes it, but errors are then ignored.
+    pub fn close(mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    /// Returns a checksum of the contents of the map, kept up to date by indexed writes and `write_at`.
+    /// Any other mutation, e.g. through `get_data_as_slice_mut` or `fill`, resets it to unknown, and the next
+    /// call then rescans the whole map.
+    pub fn running_checksum(&self) -> u64 {
+        self.resolve_checksum();
+        match self.checksum.get() {
+            Some(sum) => sum,
+            None => {
+                let sum = self.range_hash(0, self.len());
+                self.checksum.set(Some(sum));
+                sum
+            }
+        }
+    }
+
+    /// Returns the CRC32 of every page of the map data, the last one possibly being shorter.
+    /// Comparing the checksums of two runs locates the pages that differ, e.g. to find a corrupted page.
+    pub fn page_checksums(&self) -> Vec<u32> {
+        self.as_bytes()
+            .chunks(MemoryMap::granularity())
+            .map(crc32)
+            .collect()
+    }
+
+    /// Stores the CRC32 of the data in the header of a file built `with_header`, so that reopening it with
+    /// `verify_checksum` detects corruption. Later writes make the stored CRC stale until this is called again.
+    pub fn write_checksum(&self) -> io::Result<()> {
+        let file = self.backing_file()?;
+        let header = Header::read(file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if header.count != self.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The map does not cover all the elements recorded in the header",
+            ));
+        }
+        Header {
+            crc: Some(crc32(self.as_bytes())),
+            ..header
+        }
+        .write(file)
+    }
+
+    /// Returns an iterator yielding an item every time the backing file is modified through a write to it,
+    /// e.g. by another process. The iterator blocks until the next modification.
+    /// Writes through the memory map itself, from any process, are not reported, as they bypass the file.
+    pub fn watch_changes(&self) -> io::Result<impl Iterator<Item = ()>> {
+        let file = self.backing_file()?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // The link to the open file is followed, so no path needs to be kept around
+        let path = std::ffi::CString::new(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap();
+        let result =
+            unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), libc::IN_MODIFY) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(FileChanges { fd, pending: 0 })
+    }
+
+    /// Takes an exclusive advisory lock on the backing file with `flock`, waiting until it is available.
+    /// Processes that also lock the file before writing it can then never write it at the same time.
+    pub fn lock_file_exclusive(&self) -> io::Result<()> {
+        flock(self.backing_file()?, libc::LOCK_EX)
+    }
+
+    /// Releases the lock taken with `lock_file_exclusive`.
+    pub fn unlock_file(&self) -> io::Result<()> {
+        flock(self.backing_file()?, libc::LOCK_UN)
+    }
+
+    /// Copies the elements into a `Vec` and unmaps the region.
+    /// The elements are always copied, even for anonymous maps: a `Vec` frees its buffer with the global
+    /// allocator, so it cannot take over memory obtained from `mmap`.
+    pub fn into_vec(self) -> Vec<T> {
+        self._data.to_vec()
+    }
+
+    /// Whether the map may have been modified since it was built or last flushed.
+    /// Exact detection through the page tables isn't reliable, as soft-dirty bits are shared by the whole process,
+    /// so any mutable access to the map marks it as dirty.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    /// Reorders the elements in place so that the ones satisfying `pred` come first,
+    /// returning the index of the first element that doesn't. The relative order is not preserved.
+    pub fn partition_in_place<P: Fn(&T) -> bool + Sync>(&mut self, pred: P) -> usize {
+        let data = self.get_data_as_slice_mut();
+        let mut pivot = 0;
+        for i in 0..data.len() {
+            if pred(&data[i]) {
+                data.swap(pivot, i);
+                pivot += 1;
+            }
+        }
+        pivot
+    }
+
+    /// Replaces every element with the sum of itself and all the elements before it.
+    pub fn prefix_sum(&mut self)
+    where
+        T: std::ops::AddAssign,
+    {
+        let data = self.get_data_as_slice_mut();
+        for i in 1..data.len() {
+            let previous = data[i - 1];
+            data[i] += previous;
+        }
+    }
+
+    /// Removes consecutive duplicates by moving the unique elements to the front, e.g. on a sorted map.
+    /// Returns the number of unique elements. The elements past it are left unspecified, and `truncate` drops them.
+    pub fn dedup_compact(&mut self) -> usize
+    where
+        T: PartialEq,
+    {
+        let data = self.get_data_as_slice_mut();
+        if data.is_empty() {
+            return 0;
+        }
+
+        let mut len = 1;
+        for i in 1..data.len() {
+            if data[i] != data[len - 1] {
+                data[len] = data[i];
+                len += 1;
+            }
+        }
+        len
+    }
+
+    /// Returns the number of bits set to one across all elements, e.g. the cardinality of a bitmap.
+    pub fn count_set_bits(&self) -> u64
+    where
+        T: Unsigned,
+    {
+        self._data.iter().map(|v| v.count_ones() as u64).sum()
+    }
+
+    /// Splits a bit index into the element holding it and the position of the bit in that element.
+    /// Panics if the bit is past the end of the map.
+    fn bit_position(&self, bit: usize) -> (usize, u32)
+    where
+        T: Unsigned,
+    {
+        let bits_per_elem = T::BITS as usize;
+        if bit >= self.len() * bits_per_elem {
+            panic!(
+                "Bit {} is out of bounds for a map of {} bits",
+                bit,
+                self.len() * bits_per_elem
+            );
+        }
+        (bit / bits_per_elem, (bit % bits_per_elem) as u32)
+    }
+
+    /// Whether bit `bit` of the map, seen as a packed bit vector, is set.
+    pub fn bit_get(&self, bit: usize) -> bool
+    where
+        T: Unsigned,
+    {
+        let (index, n) = self.bit_position(bit);
+        self._data[index].bit(n)
+    }
+
+    /// Sets bit `bit` of the map, seen as a packed bit vector.
+    pub fn bit_set(&mut self, bit: usize)
+    where
+        T: Unsigned,
+    {
+        let (index, n) = self.bit_position(bit);
+        self[index] = self[index].with_bit(n, true);
+    }
+
+    /// Clears bit `bit` of the map, seen as a packed bit vector.
+    pub fn bit_clear(&mut self, bit: usize)
+    where
+        T: Unsigned,
+    {
+        let (index, n) = self.bit_position(bit);
+        self[index] = self[index].with_bit(n, false);
+    }
+
+    /// Converts every element from native to big-endian byte order, e.g. to share a file across architectures.
+    /// This is a no-op on big-endian targets.
+    pub fn to_big_endian(&mut self)
+    where
+        T: Integer,
+    {
+        self.iter_mut().for_each(|v| *v = v.to_be());
+    }
+
+    /// Converts every element from native to little-endian byte order.
+    /// This is a no-op on little-endian targets.
+    pub fn to_little_endian(&mut self)
+    where
+        T: Integer,
+    {
+        self.iter_mut().for_each(|v| *v = v.to_le());
+    }
+
+    /// Reads one byte of every page of the map so that it is faulted into memory ahead of use.
+    pub fn prefault_all(&self) {
+        if !self.flags.contains(MapFlags::READABLE) {
+            panic!("The map is not readable");
+        }
+
+        let (start, len) = self.region();
+        for offset in (0..len).step_by(MemoryMap::granularity()) {
+            unsafe { std::ptr::read_volatile(start.add(offset)) };
+        }
+    }
+
+    /// Returns the fraction of the pages of the map that are currently resident in memory.
+    pub fn residency(&self) -> io::Result<f64> {
+        let (start, len) = self.region();
+        let pages = len.div_ceil(MemoryMap::granularity());
+        let mut residency = vec![0u8; pages];
+
+        let result =
+            unsafe { libc::mincore(start.cast::<libc::c_void>(), len, residency.as_mut_ptr()) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let resident = residency.iter().filter(|page| *page & 1 == 1).count();
+        Ok(resident as f64 / pages as f64)
+    }
+
+    /// Returns the start and length of the mapped region, or of the pages holding the elements of a view
+    /// created with `from_raw`.
+    fn region(&self) -> (*mut u8, usize) {
+        match &self._map {
+            Some(map) => (map.data(), map.len()),
+            None => {
+                let (start, len) = self.page_span(0..self.len());
+                (start.cast::<u8>(), len)
+            }
+        }
+    }
+
+    /// Returns the start and length of the page-aligned span covering the elements in `range`.
+    fn page_span(&self, range: Range<usize>) -> (*mut libc::c_void, usize) {
+        self.assert_range(range.start, range.len());
+        let page_size = MemoryMap::granularity();
+        let size = std::mem::size_of::<T>();
+        let start = self._data.as_ptr() as usize + range.start * size;
+        let end = start + range.len() * size;
+        let aligned_start = start - start % page_size;
+        (aligned_start as *mut libc::c_void, end - aligned_start)
+    }
+
+    /// Calls `f` on every chunk of `chunk` elements in order, first advising the kernel to read ahead the
+    /// following chunk with `MADV_WILLNEED`, so that I/O overlaps with the processing of the current one.
+    pub fn scan_with_prefetch<F: FnMut(&[T])>(&self, chunk: usize, mut f: F) -> io::Result<()> {
+        for (i, values) in self._data.chunks(chunk).enumerate() {
+            let next = ((i + 1) * chunk).min(self.len())..((i + 2) * chunk).min(self.len());
+            if !next.is_empty() {
+                let (addr, len) = self.page_span(next);
+                if unsafe { libc::madvise(addr, len, libc::MADV_WILLNEED) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            f(values);
+        }
+        Ok(())
+    }
+
+    /// Locks the pages holding the elements in `range` in memory, so they are never swapped out.
+    /// Unlike locking the whole map, only the hot part of a large map is pinned.
+    pub fn lock_range(&self, range: Range<usize>) -> io::Result<()> {
+        let (addr, len) = self.page_span(range);
+        if unsafe { libc::mlock(addr, len) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Unlocks the pages holding the elements in `range`, see `lock_range`.
+    pub fn unlock_range(&self, range: Range<usize>) -> io::Result<()> {
+        let (addr, len) = self.page_span(range);
+        if unsafe { libc::munlock(addr, len) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Binds the pages of the map to NUMA nodes with `mbind`, `node_of` being called with the index of each
+    /// page, so chunks can be processed by threads on the node holding them. Pages already allocated
+    /// elsewhere are moved. Consecutive pages on the same node are bound with a single call.
+    pub fn numa_touch(&self, node_of: impl Fn(usize) -> u32) -> io::Result<()> {
+        const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+        let (start, len) = self.region();
+        let page_size = MemoryMap::granularity();
+        let nodes = (0..len.div_ceil(page_size))
+            .map(node_of)
+            .collect::<Vec<_>>();
+        let bits = libc::c_ulong::BITS as usize;
+
+        let mut page = 0;
+        for run in nodes.chunk_by(|a, b| a == b) {
+            let node = run[0] as usize;
+            let mut mask = vec![0 as libc::c_ulong; node / bits + 1];
+            mask[node / bits] |= 1 << (node % bits);
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_mbind,
+                    start.add(page * page_size),
+                    run.len() * page_size,
+                    libc::MPOL_BIND,
+                    mask.as_ptr(),
+                    mask.len() * bits + 1,
+                    MPOL_MF_MOVE,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            page += run.len();
+        }
+        Ok(())
+    }
+
+    /// Shortens the memory map to its first `len` elements, keeping the mapped region as is.
+    /// Has no effect if `len` is greater than the current length.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.capacity {
+            self.sync_pending_write();
+            let data = std::mem::take(&mut self._data);
+            self._data = &mut data[..len];
+            self.capacity = len;
+            self.cursor = self.cursor.min(len);
+            self.forget_checksum();
+        }
+    }
+
+    /// Reverses the order of the elements in place.
+    /// For file-backed maps the reversed order is persisted to the file.
+    pub fn reverse(&mut self) {
+        self.assert_writable();
+        self.mark_dirty();
+        self._data.reverse();
+    }
+
+    /// Clamps every element into `[min, max]` in place, e.g. to saturate sensor readings.
+    /// Panics if `min` is greater than `max`.
+    pub fn clamp_all(&mut self, min: T, max: T)
+    where
+        T: Ord,
+    {
+        assert!(
+            min <= max,
+            "The minimum must not be greater than the maximum"
+        );
+        self.assert_writable();
+        self.mark_dirty();
+        for v in self._data.iter_mut() {
+            *v = (*v).clamp(min, max);
+        }
+    }
+
+    /// Shuffles the elements in place, with the same order for the same `seed`.
+    #[cfg(feature = "rand")]
+    pub fn shuffle(&mut self, seed: u64) {
+        use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+        self.assert_writable();
+        self.mark_dirty();
+        self._data.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+
+    /// Sets every byte of the memory map to `byte`, regardless of the structure of `T`.
+    /// Useful for scrubbing the region or initializing it with a sentinel pattern such as `0xFF`.
+    /// As the pattern may not form a valid value of every type, e.g. `bool`, `T` must be `FileSafe`.
+    pub fn fill_bytes(&mut self, byte: u8)
+    where
+        T: FileSafe,
+    {
+        self.assert_writable();
+        self.mark_dirty();
+        unsafe { std::ptr::write_bytes(self._data.as_mut_ptr(), byte, self.capacity) };
+    }
+
+    /// Copies all the elements of `other`, which must have the same length, into the memory map.
+    pub fn copy_from_map(&mut self, other: &EasyMmap<T>) {
+        assert_eq!(
+            self.len_bytes(),
+            other.len_bytes(),
+            "The maps must have the same length"
+        );
+        self.assert_writable();
+        self.mark_dirty();
+        // Two maps never share their elements mutably, so the regions don't overlap
+        unsafe {
+            std::ptr::copy_nonoverlapping(other._data.as_ptr(), self._data.as_mut_ptr(), self.len())
+        };
+    }
+
+    /// Creates a new map over `out` holding the elements satisfying `pred`, in order.
+    /// The elements are counted first to size the file, then copied one by one, so the kept elements are
+    /// never held in memory all at once. If no element matches, the map and the file are empty.
+    pub fn filter_to_file<P: Fn(&T) -> bool>(
+        &self,
+        pred: P,
+        out: fs::File,
+    ) -> io::Result<EasyMmap<'a, T>>
+    where
+        T: FileSafe,
+    {
+        let count = self._data.iter().filter(|v| pred(v)).count();
+        let mut map = EasyMmapBuilder::new()
+            .capacity(count)
+            .readable()
+            .writable()
+            .file(out)
+            .try_build()
+            .map_err(io::Error::other)?;
+
+        for (slot, v) in map
+            .get_data_as_slice_mut()
+            .iter_mut()
+            .zip(self._data.iter().filter(|v| pred(v)))
+        {
+            *slot = *v;
+        }
+        Ok(map)
+    }
+
+    /// Copies the elements into `dest`, which must have the same length, e.g. to capture the map repeatedly
+    /// into a preallocated destination.
+    pub fn snapshot_into(&self, dest: &mut EasyMmap<T>) -> Result<(), LenMismatch> {
+        if dest.len() != self.len() {
+            return Err(LenMismatch {
+                expected: self.len(),
+                found: dest.len(),
+            });
+        }
+        dest.copy_from_map(self);
+        Ok(())
+    }
+
+    /// Exchanges the underlying storage of the two maps without copying, e.g. to trade the roles of two
+    /// buffers. Everything tied to the storage is swapped, only `trim_on_drop` and `sync_on_write` stay
+    /// with each handle.
+    pub fn swap_with(&mut self, other: &mut EasyMmap<'a, T>) {
+        std::mem::swap(&mut self._map, &mut other._map);
+        std::mem::swap(&mut self._data, &mut other._data);
+        std::mem::swap(&mut self.capacity, &mut other.capacity);
+        std::mem::swap(&mut self._file, &mut other._file);
+        std::mem::swap(&mut self.file_offset, &mut other.file_offset);
+        std::mem::swap(&mut self._options, &mut other._options);
+        std::mem::swap(&mut self.flags, &mut other.flags);
+        self.dirty.swap(&other.dirty);
+        self.checksum.swap(&other.checksum);
+        self.checksum_pending.swap(&other.checksum_pending);
+        self.sync_pending.swap(&other.sync_pending);
+        std::mem::swap(&mut self.cursor, &mut other.cursor);
+        std::mem::swap(&mut self.huge_pages, &mut other.huge_pages);
+    }
+
+    /// Returns the index and both values of every element that differs from `other`, which must have the same
+    /// length, e.g. to find where two maps diverged.
+    pub fn diff(&self, other: &EasyMmap<T>) -> Vec<(usize, T, T)>
+    where
+        T: PartialEq,
+    {
+        assert_eq!(
+            self.len(),
+            other.len(),
+            "The maps must have the same length"
+        );
+        self._data
+            .iter()
+            .zip(other._data.iter())
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, (a, b))| (i, *a, *b))
+            .collect()
+    }
+
+    /// Returns a copy of the elements, to be restored later with `rollback`.
+    pub fn checkpoint(&self) -> Vec<T> {
+        self._data.to_vec()
+    }
+
+    /// Restores the elements saved by `checkpoint`, which must have the same length as the map.
+    pub fn rollback(&mut self, checkpoint: &[T]) {
+        assert_eq!(
+            self.len(),
+            checkpoint.len(),
+            "The checkpoint must have the same length as the map"
+        );
+        self.assert_writable();
+        self.mark_dirty();
+        self._data.copy_from_slice(checkpoint);
+    }
+
+    /// Creates a new anonymous memory map holding the elements of `self` followed by the ones of `other`.
+    pub fn concat(&self, other: &EasyMmap<T>) -> EasyMmap<'a, T> {
+        let map = EasyMmapBuilder::new()
+            .capacity(self.len() + other.len())
+            .readable()
+            .writable()
+            .build();
+
+        let (head, tail) = map._data.split_at_mut(self.len());
+        head.copy_from_slice(self._data);
+        tail.copy_from_slice(other._data);
+
+        map
+    }
+
+    /// Splits the elements into `n` independent anonymous read-write maps, e.g. one per worker thread.
+    /// The chunks have the same length, except for the last one which also holds the remaining elements.
+    /// Panics if `n` is zero or greater than the length.
+    pub fn into_chunks(self, n: usize) -> Vec<EasyMmap<'a, T>> {
+        assert!(
+            n != 0 && n <= self.len(),
+            "Cannot split {} elements into {} chunks",
+            self.len(),
+            n
+        );
+        let size = self.len() / n;
+        (0..n)
+            .map(|i| {
+                let end = if i == n - 1 {
+                    self.len()
+                } else {
+                    (i + 1) * size
+                };
+                let values = &self._data[i * size..end];
+                let mut map = EasyMmapBuilder::new()
+                    .capacity(values.len())
+                    .readable()
+                    .writable()
+                    .build();
+                map.write_at(0, values);
+                map
+            })
+            .collect()
+    }
+
+    /// Creates a new anonymous read-write map holding the items of a parallel iterator, in order.
+    /// Complements `EasyMmapBuilder::collect_into` for items that are expensive to produce.
+    /// An empty iterator gives an empty map.
+    #[cfg(feature = "rayon")]
+    pub fn from_par_iter<I: IntoParallelIterator<Item = T>>(iter: I) -> EasyMmap<'a, T>
+    where
+        T: Send + Sync,
+    {
+        let values = iter.into_par_iter().collect::<Vec<_>>();
+        let mut map = EasyMmapBuilder::new()
+            .capacity(values.len())
+            .readable()
+            .writable()
+            .build();
+        map.write_at(0, &values);
+        map
+    }
+
+    /// Turns the map into a read-only handle, e.g. to share a completed map across threads.
+    /// Unlike `seal`, the pages are not protected: immutability is only guaranteed by the type.
+    pub fn freeze(self) -> FrozenMmap<'a, T> {
+        FrozenMmap { map: self }
+    }
+
+    /// Converts the map into a map of `U` over the same region and file, e.g. to map a file as bytes and
+    /// then reinterpret it as a struct type. Panics if the length in bytes is not a multiple of the size of `U`,
+    /// or if the data is not aligned for `U`.
+    ///
+    /// # Safety
+    ///
+    /// - The bytes of every element must be a valid value of `U`: types with invalid bit patterns, such as
+    ///   `bool`, `char`, enums or references, must not be read from arbitrary data.
+    /// - `U` must not have padding if the map is written and backed by a file, as the padding bytes are
+    ///   uninitialized.
+    pub unsafe fn transmute_elements<U: Copy>(self) -> EasyMmap<'a, U> {
+        let size = std::mem::size_of::<U>();
+        assert!(size != 0, "Zero-sized types are not supported");
+        assert!(
+            self.len_bytes() % size == 0,
+            "The map length of {} bytes is not a multiple of the size of {}",
+            self.len_bytes(),
+            std::any::type_name::<U>()
+        );
+        assert!(
+            self._data.as_ptr().cast::<U>().is_aligned(),
+            "The map data is not aligned for {}",
+            std::any::type_name::<U>()
+        );
+
+        let capacity = self.len_bytes() / size;
+        let this = std::mem::ManuallyDrop::new(self);
+        // The fields are moved out exactly once, and `this` is never dropped
+        unsafe {
+            EasyMmap {
+                _map: std::ptr::read(&this._map),
+                _data: std::slice::from_raw_parts_mut(this._data.as_ptr() as *mut U, capacity),
+                capacity,
+                _file: std::ptr::read(&this._file),
+                file_offset: this.file_offset,
+                _options: std::ptr::read(&this._options),
+                flags: this.flags,
+                trim_on_drop: this.trim_on_drop,
+                dirty: Cell::new(this.dirty.get()),
+                checksum: Cell::new(None),
+                checksum_pending: Cell::new(None),
+                sync_on_write: this.sync_on_write,
+                sync_pending: Cell::new(None),
+                cursor: this.cursor * std::mem::size_of::<T>() / size,
+                huge_pages: this.huge_pages,
+            }
+        }
+    }
+}
+
+/// The structure can be indexed similarly to an array.
+/// Example:
+/// ```
+/// let mut mmap = easy_mmap::EasyMmapBuilder::new()
+///                     .options(&[
+///                         mmap::MapOption::MapWritable,
+///                         mmap::MapOption::MapReadable,
+///                     ])
+///                     .capacity(10)
+///                     .build();
+/// mmap[0] = 1;
+/// println!("{}", mmap[0]);
+/// ```
+impl<'a, T> Index<usize> for EasyMmap<'a, T>
+where
+    T: Copy,
+{
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        if index >= self.len() {
+            panic!(
+                "Index {} is out of bounds for type {}",
+                index,
+                std::any::type_name::<T>(),
+            );
+        };
+        &self._data[index]
+    }
+}
+
+/// The structure can be indexed an array or slice.
+/// See the `Index` trait for an example.
+impl<'a, T> IndexMut<usize> for EasyMmap<'a, T>
+where
+    T: Copy,
+{
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        if index >= self.len() {
+            panic!(
+                "Index {} is out of bounds for type {}",
+                index,
+                std::any::type_name::<T>(),
+            )
+        }
+        self.assert_writable();
+        self.dirty.set(true);
+        // The new value is only known once the reference is released, so it is added back lazily
+        self.resolve_checksum();
+        if let Some(sum) = self.checksum.get() {
+            self.checksum
+                .set(Some(sum ^ element_hash(index, &self._data[index])));
+            self.checksum_pending.set(Some(index));
+        }
+        if self.sync_on_write {
+            self.sync_pending_write();
+            self.sync_pending.set(Some(index));
+        }
+        &mut self._data[index]
+    }
+}
+
+/// The structure can be sliced with inclusive ranges, e.g. `&map[1..=3]`.
+impl<'a, T> Index<RangeInclusive<usize>> for EasyMmap<'a, T>
+where
+    T: Copy,
+{
+    type Output = [T];
+
+    fn index(&self, range: RangeInclusive<usize>) -> &Self::Output {
+        &self._data[self.inclusive_range(*range.start(), *range.end())]
+    }
+}
+
+impl<'a, T> IndexMut<RangeInclusive<usize>> for EasyMmap<'a, T>
+where
+    T: Copy,
+{
+    fn index_mut(&mut self, range: RangeInclusive<usize>) -> &mut Self::Output {
+        let range = self.inclusive_range(*range.start(), *range.end());
+        self.assert_writable();
+        self.mark_dirty();
+        &mut self._data[range]
+    }
+}
+
+impl<'a, T> Index<RangeToInclusive<usize>> for EasyMmap<'a, T>
+where
+    T: Copy,
+{
+    type Output = [T];
+
+    fn index(&self, range: RangeToInclusive<usize>) -> &Self::Output {
+        &self._data[self.inclusive_range(0, range.end)]
+    }
+}
+
+impl<'a, T> IndexMut<RangeToInclusive<usize>> for EasyMmap<'a, T>
+where
+    T: Copy,
+{
+    fn index_mut(&mut self, range: RangeToInclusive<usize>) -> &mut Self::Output {
+        let range = self.inclusive_range(0, range.end);
+        self.assert_writable();
+        self.mark_dirty();
+        &mut self._data[range]
+    }
+}
+
+/// Maps `element_count` elements of `file`, starting at element `element_offset`, as a shared read-write map.
+/// The offset doesn't need to be page-aligned, and the file is not resized, so it must already hold the elements.
+pub fn map_region<T: Copy + FileSafe>(
+    file: fs::File,
+    element_offset: usize,
+    element_count: usize,
+) -> io::Result<EasyMmap<'static, T>> {
+    let size = std::mem::size_of::<T>();
+    let overflow = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The requested region is larger than the address space",
+        )
+    };
+    let byte_offset = element_offset.checked_mul(size).ok_or_else(overflow)?;
+    let end = element_count
+        .checked_mul(size)
+        .and_then(|len| len.checked_add(byte_offset))
+        .ok_or_else(overflow)?;
+    if file.metadata()?.len() < end as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The file is too short for the requested region",
+        ));
+    }
+
+    // Offsets into the file must be page-aligned, so map from the start of the page
+    let page_offset = byte_offset - byte_offset % MemoryMap::granularity();
+    let options = [
+        MapOption::MapReadable,
+        MapOption::MapWritable,
+        MapOption::MapFd(file.as_raw_fd()),
+        MapOption::MapOffset(page_offset),
+        MapOption::MapNonStandardFlags(libc::MAP_SHARED),
+    ];
+
+    let mut map = EasyMmap::new_view(
+        byte_offset - page_offset,
+        element_count,
+        &options,
+        Some(file),
+    )
+    .map_err(io::Error::other)?;
+    map.file_offset = byte_offset as u64;
+    Ok(map)
+}
+
+/// Creates a read-only map holding the elements of all the `files` one after the other, e.g. for a dataset
+/// split across several files. Separate mappings can't be made contiguous in general, so the elements are
+/// copied into a single anonymous region. A trailing partial element in a file is ignored.
+pub fn concat_files<T: Copy + FileSafe>(files: Vec<fs::File>) -> io::Result<EasyMmap<'static, T>> {
+    let mut parts = Vec::new();
+    for file in files {
+        if file.metadata()?.len() < std::mem::size_of::<T>() as u64 {
+            continue;
+        }
+        let part = EasyMmapBuilder::<T>::new()
+            .readable()
+            .file(file)
+            .capacity_from_file()
+            .try_build()
+            .map_err(io::Error::other)?;
+        parts.push(part);
+    }
+
+    let mut map = EasyMmapBuilder::new()
+        .capacity(parts.iter().map(|part| part.len()).sum())
+        .readable()
+        .writable()
+        .try_build()
+        .map_err(io::Error::other)?;
+    let mut offset = 0;
+    for part in &parts {
+        map.write_at(offset, part.get_data_as_slice());
+        offset += part.len();
+    }
+    map.protect_read_only()?;
+    Ok(map)
+}
+
+/// Reads all the bytes of `reader`, e.g. a pipe or stdin, into a new anonymous read-write map.
+/// Fails if the number of bytes read is not a multiple of the size of `T`. Empty input gives an empty map.
+pub fn from_reader<T: Copy + FileSafe, R: io::Read>(
+    mut reader: R,
+) -> io::Result<EasyMmap<'static, T>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let element_size = std::mem::size_of::<T>();
+    if bytes.len() % element_size != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Read {} bytes, which is not a multiple of the element size {}",
+                bytes.len(),
+                element_size
+            ),
+        ));
+    }
+
+    let mut map = EasyMmapBuilder::new()
+        .capacity(bytes.len() / element_size)
+        .readable()
+        .writable()
+        .try_build()
+        .map_err(io::Error::other)?;
+    map.as_bytes_mut().copy_from_slice(&bytes);
+    Ok(map)
+}
+
+/// Truncates `file` down to a whole number of elements of `T`, dropping a partially written trailing element,
+/// e.g. after a crash. Returns the number of elements left in the file.
+pub fn repair_file<T>(file: &fs::File) -> io::Result<usize> {
+    let element_size = std::mem::size_of::<T>().max(1) as u64;
+    let len = file.metadata()?.len();
+    if len % element_size != 0 {
+        file.set_len(len - len % element_size)?;
+    }
+    Ok((len / element_size) as usize)
+}
+
+/// Creates a single `u64` in anonymous memory shared with child processes, set to `initial`.
+/// Combined with `as_atomic`, this is a ready-made counter across `fork`.
+pub fn shared_atomic_u64(initial: u64) -> EasyMmap<'static, u64> {
+    let mut map = EasyMmapBuilder::new()
+        .capacity(1)
+        .readable()
+        .writable()
+        .add_option(MapOption::MapNonStandardFlags(
+            libc::MAP_SHARED | libc::MAP_ANON,
+        ))
+        .build();
+    map[0] = initial;
+    map
+}
+
+/// FNV-1a hash of an element and its index, so that equal elements in different slots don't cancel out.
+fn element_hash<T>(index: usize, value: &T) -> u64 {
+    let bytes = unsafe {
+        std::slice::from_raw_parts((value as *const T).cast::<u8>(), std::mem::size_of::<T>())
+    };
+    index
+        .to_le_bytes()
+        .iter()
+        .chain(bytes)
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Applies the `flock` `operation` to `file`.
+fn flock(file: &fs::File, operation: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Creates an anonymous file that can be sealed, see `EasyMmapBuilder::memfd`.
+fn create_memfd(name: &str) -> io::Result<fs::File> {
+    let name =
+        std::ffi::CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+/// CRC32 (IEEE) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    !crc32_update(!0, bytes)
+}
+
+/// Feeds `bytes` into the running, non-inverted CRC32 state `crc`.
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// CRC32 of the `len` bytes of `file` starting at `offset`, read in chunks.
+fn file_crc32(file: &fs::File, offset: usize, len: usize) -> io::Result<u32> {
+    let mut buf = vec![0u8; len.min(1 << 20)];
+    let mut crc = !0;
+    let mut pos = 0;
+    while pos < len {
+        let n = (len - pos).min(buf.len());
+        file.read_exact_at(&mut buf[..n], (offset + pos) as u64)?;
+        crc = crc32_update(crc, &buf[..n]);
+        pos += n;
+    }
+    Ok(!crc)
+}
+
+/// Removes duplicated options, keeping the protection flags once each at the front and only the last
+/// occurrence of every other kind of option, which is the one `mmap` uses.
+fn normalize_options(options: &[MapOption]) -> Vec<MapOption> {
+    let flags = MapFlags::from_options(options);
+    let mut normalized = Vec::new();
+    for (flag, option) in [
+        (MapFlags::READABLE, MapOption::MapReadable),
+        (MapFlags::WRITABLE, MapOption::MapWritable),
+        (MapFlags::EXECUTABLE, MapOption::MapExecutable),
+    ] {
+        if flags.contains(flag) {
+            normalized.push(option);
+        }
+    }
+
+    let kind = std::mem::discriminant::<MapOption>;
+    for (i, option) in options.iter().enumerate() {
+        let prot = matches!(
+            option,
+            MapOption::MapReadable | MapOption::MapWritable | MapOption::MapExecutable
+        );
+        if !prot && !options[i + 1..].iter().any(|o| kind(o) == kind(option)) {
+            normalized.push(*option);
+        }
+    }
+    normalized
+}
+
+/// Whether `options` map the region with `MAP_HUGETLB`, taking the last flags as `mmap` does.
+fn uses_huge_pages(options: &[MapOption]) -> bool {
+    options.iter().rev().find_map(|o| match o {
+        MapOption::MapNonStandardFlags(flags) => Some(flags & libc::MAP_HUGETLB != 0),
+        _ => None,
+    }) == Some(true)
+}
+
+/// Returns the default huge page size from `/proc/meminfo`, or 2 MiB if it cannot be read.
+fn huge_page_size() -> usize {
+    fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("Hugepagesize:"))
+                .and_then(|size| size.trim().strip_suffix("kB"))
+                .and_then(|kb| kb.trim().parse::<usize>().ok())
+        })
+        .map_or(2 << 20, |kb| kb << 10)
+}
+
+/// Synchronizes the whole mapped region with its file.
+fn msync(map: &MemoryMap, flags: libc::c_int) -> io::Result<()> {
+    let result = unsafe { libc::msync(map.data().cast::<libc::c_void>(), map.len(), flags) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Flushes file-backed maps that have been modified, and trims the backing file to the length of the map
+/// if requested with `trim_on_drop`.
+impl<'a, T> Drop for EasyMmap<'a, T> {
+    fn drop(&mut self) {
+        if let (true, Some(map), Some(_)) = (self.dirty.get(), &self._map, &self._file) {
+            let _ = msync(map, libc::MS_SYNC);
+        }
+
+        if let (true, Some(file)) = (self.trim_on_drop, &self._file) {
+            let _ =
+                file.set_len(self.file_offset + (self.capacity * std::mem::size_of::<T>()) as u64);
+        }
+    }
+}
+
+/// The modifications of a backing file, see `EasyMmap::watch_changes`.
+struct FileChanges {
+    fd: OwnedFd,
+    pending: usize,
+}
+
+impl Iterator for FileChanges {
+    type Item = ();
+
+    fn next(&mut self) -> Option<()> {
+        while self.pending == 0 {
+            // A single read returns every queued event
+            let mut buffer = [0u8; 4096];
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast::<libc::c_void>(),
+                    buffer.len(),
+                )
+            };
+            if read <= 0 {
+                return None;
+            }
+
+            let header = std::mem::size_of::<libc::inotify_event>();
+            let mut offset = 0;
+            while offset + header <= read as usize {
+                let event = unsafe {
+                    std::ptr::read_unaligned(
+                        buffer.as_ptr().add(offset).cast::<libc::inotify_event>(),
+                    )
+                };
+                if event.mask & libc::IN_MODIFY != 0 {
+                    self.pending += 1;
+                }
+                offset += header + event.len as usize;
+            }
+        }
+
+        self.pending -= 1;
+        Some(())
+    }
+}
+
+/// Prints a short summary of the memory map, without its contents.
+/// Example: `EasyMmap<u32>[10 elements, 40 bytes, file-backed]`.
+impl<'a, T> fmt::Display for EasyMmap<'a, T>
+where
+    T: Copy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "EasyMmap<{}>[{} elements, {} bytes, {}]",
+            std::any::type_name::<T>(),
+            self.len(),
+            self.len_bytes(),
+            if self.is_file_backed() {
+                "file-backed"
+            } else {
+                "anonymous"
+            }
+        )
+    }
+}
+
+/// A memory map that can no longer be modified, created with `EasyMmap::freeze`.
+/// Only read accessors are available:
+/// ```compile_fail
+/// let map = easy_mmap::EasyMmapBuilder::<u32>::new()
+///     .capacity(4)
+///     .readable()
+///     .writable()
+///     .build();
+/// let mut frozen = map.freeze();
+/// frozen[0] = 1;
+/// ```
+pub struct FrozenMmap<'a, T> {
+    map: EasyMmap<'a, T>,
+}
+
+// The bookkeeping cells of the map are only updated by mutations, which a frozen map doesn't expose
+unsafe impl<'a, T: Sync> Sync for FrozenMmap<'a, T> {}
+
+impl<'a, T> FrozenMmap<'a, T>
+where
+    T: Copy,
+{
+    /// How many elements are stored in the memory map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether the memory map holds no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.len() == 0
+    }
+
+    /// Returns the element at `index`, or `None` if it is out of bounds.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.map._data.get(index)
+    }
+
+    /// Returns an iterator over the elements of the memory map.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.map.iter()
+    }
+
+    /// Returns a parallel iterator over the elements of the memory map.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &T> where T: Send + Sync {
+        self.map.par_iter()
+    }
+
+    /// Returns a read-only slice of the memory map data.
+    pub fn get_data_as_slice(&self) -> &[T] {
+        self.map.get_data_as_slice()
+    }
+
+    /// Returns the memory map data as raw bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.map.as_bytes()
+    }
+}
+
+impl<'a, T> Index<usize> for FrozenMmap<'a, T>
+where
+    T: Copy,
+{
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        // Reads the data directly, so that concurrent reads never touch the bookkeeping cells
+        &self.map._data[index]
+    }
+}
+
+/// Two memory maps of the same length used for ping-pong buffering: one is read as the front buffer while
+/// the next contents are written into the back buffer, and `swap` exchanges them.
+pub struct DoubleBuffer<'a, T> {
+    maps: [EasyMmap<'a, T>; 2],
+    front: usize,
+    flush_on_swap: bool,
+}
+
+impl<'a, T> DoubleBuffer<'a, T>
+where
+    T: Copy,
+{
+    /// Creates a double buffer from two maps of the same length, the first one being the front buffer.
+    pub fn new(front: EasyMmap<'a, T>, back: EasyMmap<'a, T>) -> DoubleBuffer<'a, T> {
+        assert_eq!(
+            front.len(),
+            back.len(),
+            "The buffers must have the same length"
+        );
+        DoubleBuffer {
+            maps: [front, back],
+            front: 0,
+            flush_on_swap: false,
+        }
+    }
+
+    /// Creates a double buffer of `capacity` elements over two files, sized as with `EasyMmapBuilder::file`.
+    pub fn with_files(
+        capacity: usize,
+        front: fs::File,
+        back: fs::File,
+    ) -> Result<DoubleBuffer<'a, T>, EasyMmapError>
+    where
+        T: FileSafe,
+    {
+        let build = |file| {
+            EasyMmapBuilder::new()
+                .capacity(capacity)
+                .readable()
+                .writable()
+                .file(file)
+                .try_build()
+        };
+        Ok(DoubleBuffer::new(build(front)?, build(back)?))
+    }
+
+    /// Flushes the back buffer when it is swapped to the front, so the published contents reach its file.
+    pub fn flush_on_swap(mut self) -> DoubleBuffer<'a, T> {
+        self.flush_on_swap = true;
+        self
+    }
+
+    /// The buffer currently being read.
+    pub fn front(&self) -> &EasyMmap<'a, T> {
+        &self.maps[self.front]
+    }
+
+    /// The buffer currently being written.
+    pub fn back_mut(&mut self) -> &mut EasyMmap<'a, T> {
+        &mut self.maps[1 - self.front]
+    }
+
+    /// Makes the back buffer the front one and the other way around.
+    pub fn swap(&mut self) -> io::Result<()> {
+        if self.flush_on_swap {
+            self.back_mut().flush()?;
+        }
+        self.front = 1 - self.front;
+        Ok(())
+    }
+}
+
+/// A normalized copy of the options a memory map was built with.
+/// `MapOption` is neither comparable nor printable, so the relevant flags are kept here instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MapFlags(u8);
+
+impl MapFlags {
+    /// The mapped region can be read.
+    pub const READABLE: MapFlags = MapFlags(1);
+    /// The mapped region can be written.
+    pub const WRITABLE: MapFlags = MapFlags(1 << 1);
+    /// The mapped region can be executed.
+    pub const EXECUTABLE: MapFlags = MapFlags(1 << 2);
+    /// The mapped region is shared (`MAP_SHARED`) rather than private.
+    pub const SHARED: MapFlags = MapFlags(1 << 3);
+
+    /// Extracts the flags from a list of options, in the same order `mmap` applies them.
+    fn from_options(options: &[MapOption]) -> MapFlags {
+        let mut flags = MapFlags::default();
+        for option in options {
+            match *option {
+                MapOption::MapReadable => flags |= MapFlags::READABLE,
+                MapOption::MapWritable => flags |= MapFlags::WRITABLE,
+                MapOption::MapExecutable => flags |= MapFlags::EXECUTABLE,
+                // Non-standard flags replace the previous ones
+                MapOption::MapNonStandardFlags(f) => {
+                    flags.0 &= !MapFlags::SHARED.0;
+                    if f & libc::MAP_SHARED != 0 {
+                        flags |= MapFlags::SHARED;
+                    }
+                }
+                _ => {}
+            }
+        }
+        flags
+    }
+
+    /// Whether all the flags in `other` are set.
+    pub fn contains(&self, other: MapFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for MapFlags {
+    type Output = MapFlags;
+
+    fn bitor(self, rhs: MapFlags) -> MapFlags {
+        MapFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MapFlags {
+    fn bitor_assign(&mut self, rhs: MapFlags) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Errors that can happen while creating a memory map.
+#[derive(Debug)]
+pub enum EasyMmapError {
+    /// An operation on the backing file failed.
+    Io(io::Error),
+    /// The underlying `mmap` call failed.
+    Map(MapError),
+    /// The data pointer does not satisfy the requested alignment.
+    Misaligned(usize),
+    /// The map was requested both writable and executable without `allow_write_execute`.
+    WriteExecute,
+    /// The element type has a size of zero.
+    ZeroSizedType,
+    /// A file was given while the options already contain a `MapFd`.
+    ConflictingFd,
+    /// The map was not built writable.
+    NotWritable,
+    /// The requested capacity, in elements, does not fit in memory.
+    CapacityOverflow(usize),
+    /// The capacity in bytes is not a multiple of the element size.
+    CapacityNotDivisible {
+        /// The requested capacity in bytes.
+        bytes: usize,
+        /// The size of the element type.
+        element_size: usize,
+    },
+    /// The map protection requires an access mode the file was not opened with, e.g. a writable map over
+    /// a file opened read-only.
+    ProtModeMismatch,
+    /// The file does not start with a valid header, see `with_header`.
+    InvalidHeader,
+    /// The header of the file was written for elements of a different size.
+    ElementSizeMismatch {
+        /// The size of the element type of the map.
+        expected: usize,
+        /// The element size recorded in the header.
+        found: usize,
+    },
+    /// The map could not be placed at the requested address, see `at_address`.
+    AddressUnavailable(usize),
+    /// The data of the file does not match the CRC32 stored in its header, see `verify_checksum`.
+    ChecksumMismatch {
+        /// The CRC32 stored in the header.
+        expected: u32,
+        /// The CRC32 of the data of the file.
+        found: u32,
+    },
+    /// The existing file does not have the size required by the capacity, see `mismatch_policy`.
+    FileSizeMismatch {
+        /// The size in bytes required by the capacity.
+        expected: usize,
+        /// The size in bytes of the file.
+        found: usize,
+    },
+}
+
+impl fmt::Display for EasyMmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EasyMmapError::Io(e) => write!(f, "I/O error: {}", e),
+            EasyMmapError::Map(e) => write!(f, "mmap error: {}", e),
+            EasyMmapError::Misaligned(align) => {
+                write!(f, "data pointer is not aligned to {} bytes", align)
+            }
+            EasyMmapError::WriteExecute => write!(f, "map cannot be both writable and executable"),
+            EasyMmapError::ZeroSizedType => write!(f, "zero-sized types are not supported"),
+            EasyMmapError::ConflictingFd => {
+                write!(f, "both a file and a MapFd option were given")
+            }
+            EasyMmapError::NotWritable => write!(f, "map is not writable"),
+            EasyMmapError::CapacityOverflow(capacity) => {
+                write!(f, "capacity of {} elements is too large", capacity)
+            }
+            EasyMmapError::CapacityNotDivisible {
+                bytes,
+                element_size,
+            } => write!(
+                f,
+                "capacity of {} bytes is not a multiple of the element size {}",
+                bytes, element_size
+            ),
+            EasyMmapError::ProtModeMismatch => {
+                write!(f, "map protection exceeds the access mode of the file")
+            }
+            EasyMmapError::InvalidHeader => write!(f, "file does not start with a valid header"),
+            EasyMmapError::ElementSizeMismatch { expected, found } => write!(
+                f,
+                "file header records elements of {} bytes, but the map holds elements of {} bytes",
+                found, expected
+            ),
+            EasyMmapError::AddressUnavailable(addr) => {
+                write!(f, "map cannot be placed at address {:#x}", addr)
+            }
+            EasyMmapError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "file data has CRC32 {:#010x}, but its header records {:#010x}",
+                found, expected
+            ),
+            EasyMmapError::FileSizeMismatch { expected, found } => write!(
+                f,
+                "file has {} bytes, but the capacity requires {} bytes",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl Error for EasyMmapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EasyMmapError::Io(e) => Some(e),
+            EasyMmapError::Map(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EasyMmapError {
+    fn from(e: io::Error) -> Self {
+        EasyMmapError::Io(e)
+    }
+}
+
+impl From<MapError> for EasyMmapError {
+    fn from(e: MapError) -> Self {
+        EasyMmapError::Map(e)
+    }
+}
+
+/// Error returned when accessing an index past the end of a memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    /// The index that was requested.
+    pub index: usize,
+    /// The length of the memory map.
+    pub len: usize,
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Index {} is out of bounds for a map of length {}",
+            self.index, self.len
+        )
+    }
+}
+
+impl Error for OutOfBounds {}
+
+/// Error returned when two memory maps that must have the same length don't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LenMismatch {
+    /// The length of the source map.
+    pub expected: usize,
+    /// The length of the destination map.
+    pub found: usize,
+}
+
+impl fmt::Display for LenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected a map of length {}, found one of length {}",
+            self.expected, self.found
+        )
+    }
+}
+
+impl Error for LenMismatch {}
+
+/// Magic bytes at the start of files written with a header.
+const HEADER_MAGIC: [u8; 8] = *b"EASYMMAP";
+/// Version of the header layout.
+const HEADER_VERSION: u32 = 1;
+/// Bytes reserved for the header, enough to keep the data aligned for any element type.
+const HEADER_LEN: usize = 64;
+
+/// Metadata stored at the start of files built with `with_header`.
+/// Layout: magic (8 bytes), version (4), flags (4), element size (8), element count (8), data CRC32 (4),
+/// all little-endian. The CRC is only meaningful with the `HEADER_CRC` flag set.
+struct Header {
+    element_size: usize,
+    count: usize,
+    crc: Option<u32>,
+}
+
+/// Header flag set when the header holds a CRC32 of the data, see `EasyMmap::write_checksum`.
+const HEADER_CRC: u32 = 1;
+
+impl Header {
+    fn read(file: &fs::File) -> Result<Header, EasyMmapError> {
+        let mut bytes = [0u8; 36];
+        if file.read_exact_at(&mut bytes, 0).is_err()
+            || bytes[..8] != HEADER_MAGIC
+            || u32::from_le_bytes(bytes[8..12].try_into().unwrap()) != HEADER_VERSION
+        {
+            return Err(EasyMmapError::InvalidHeader);
+        }
+
+        Ok(Header {
+            element_size: u64::from_le_bytes(bytes[16..24].try_into().unwrap()) as usize,
+            count: u64::from_le_bytes(bytes[24..32].try_into().unwrap()) as usize,
+            crc: (u32::from_le_bytes(bytes[12..16].try_into().unwrap()) & HEADER_CRC != 0)
+                .then(|| u32::from_le_bytes(bytes[32..36].try_into().unwrap())),
+        })
+    }
+
+    fn write(&self, file: &fs::File) -> io::Result<()> {
+        let mut bytes = [0u8; 36];
+        bytes[..8].copy_from_slice(&HEADER_MAGIC);
+        bytes[8..12].copy_from_slice(&HEADER_VERSION.to_le_bytes());
+        if let Some(crc) = self.crc {
+            bytes[12..16].copy_from_slice(&HEADER_CRC.to_le_bytes());
+            bytes[32..36].copy_from_slice(&crc.to_le_bytes());
+        }
+        bytes[16..24].copy_from_slice(&(self.element_size as u64).to_le_bytes());
+        bytes[24..32].copy_from_slice(&(self.count as u64).to_le_bytes());
+        file.write_all_at(&bytes, 0)
+    }
+}
+
+/// Element types that can be mapped from files. Without the `pod` feature, this holds for every type.
+/// With it, the type must be `zerocopy::FromBytes + zerocopy::IntoBytes`, rejecting types with padding,
+/// pointers or invalid bit patterns, which are unsound to read back from disk. Anonymous maps are not constrained.
+#[cfg(not(feature = "pod"))]
+pub trait FileSafe {}
+
+#[cfg(not(feature = "pod"))]
+impl<T> FileSafe for T {}
+
+/// Element types that can be mapped from files, i.e. `zerocopy::FromBytes + zerocopy::IntoBytes`.
+/// Types with padding, pointers or invalid bit patterns are unsound to read back from disk, so they are
+/// rejected for file-backed maps. Anonymous maps are not constrained.
+///
+/// ```compile_fail
+/// #[derive(Clone, Copy)]
+/// struct Padded {
+///     flag: u8,
+///     value: u32,
+/// }
+///
+/// let file = std::fs::File::create("padded.bin").unwrap();
+/// let map = easy_mmap::EasyMmapBuilder::<Padded>::new().capacity(1).file(file).build();
+/// ```
+///
+/// The same holds when mapping a descriptor through the raw options:
+///
+/// ```compile_fail
+/// use std::os::unix::io::AsRawFd;
+///
+/// let file = std::fs::File::create("padded.bin").unwrap();
+/// let map = easy_mmap::EasyMmapBuilder::<bool>::new()
+///     .capacity(1)
+///     .add_option(mmap::MapOption::MapFd(file.as_raw_fd()))
+///     .build();
+/// ```
+#[cfg(feature = "pod")]
+pub trait FileSafe: zerocopy::FromBytes + zerocopy::IntoBytes {}
+
+#[cfg(feature = "pod")]
+impl<T: zerocopy::FromBytes + zerocopy::IntoBytes> FileSafe for T {}
+
+/// Primitive integer types, which unlock integer-specific operations on memory maps.
+pub trait Integer: Copy {
+    /// Converts the integer from native to big-endian byte order.
+    fn to_be(self) -> Self;
+    /// Converts the integer from native to little-endian byte order.
+    fn to_le(self) -> Self;
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl Integer for $t {
+                fn to_be(self) -> Self {
+                    <$t>::to_be(self)
+                }
+
+                fn to_le(self) -> Self {
+                    <$t>::to_le(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// Integer types with an atomic counterpart, which allow concurrent updates of shared maps.
+pub trait AtomicInteger: Integer {
+    /// The atomic type with the same in-memory representation.
+    type Atomic;
+
+    /// Stores `new` in `atomic` if it holds `current`, see the `compare_exchange` method of the atomic types.
+    fn compare_exchange(atomic: &Self::Atomic, current: Self, new: Self) -> Result<Self, Self>;
+}
+
+macro_rules! impl_atomic_integer {
+    ($($t:ty => $atomic:ty),*) => {
+        $(
+            impl AtomicInteger for $t {
+                type Atomic = $atomic;
+
+                fn compare_exchange(atomic: &$atomic, current: $t, new: $t) -> Result<$t, $t> {
+                    atomic.compare_exchange(
+                        current,
+                        new,
+                        std::sync::atomic::Ordering::SeqCst,
+                        std::sync::atomic::Ordering::SeqCst,
+                    )
+                }
+            }
+        )*
+    };
+}
+
+impl_atomic_integer!(
+    u8 => std::sync::atomic::AtomicU8,
+    u16 => std::sync::atomic::AtomicU16,
+    u32 => std::sync::atomic::AtomicU32,
+    u64 => std::sync::atomic::AtomicU64,
+    usize => std::sync::atomic::AtomicUsize,
+    i8 => std::sync::atomic::AtomicI8,
+    i16 => std::sync::atomic::AtomicI16,
+    i32 => std::sync::atomic::AtomicI32,
+    i64 => std::sync::atomic::AtomicI64,
+    isize => std::sync::atomic::AtomicIsize
+);
+
+/// Primitive unsigned integer types, which allow using a memory map as a bit set.
+pub trait Unsigned: Integer {
+    /// The size of the type in bits.
+    const BITS: u32;
+
+    /// Returns the number of bits set to one.
+    fn count_ones(self) -> u32;
+
+    /// Whether bit `n` is set.
+    fn bit(self, n: u32) -> bool;
+
+    /// Returns a copy of the integer with bit `n` set to `value`.
+    fn with_bit(self, n: u32, value: bool) -> Self;
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl Unsigned for $t {
+                const BITS: u32 = <$t>::BITS;
+
+                fn count_ones(self) -> u32 {
+                    <$t>::count_ones(self)
+                }
+
+                fn bit(self, n: u32) -> bool {
+                    (self >> n) & 1 == 1
+                }
+
+                fn with_bit(self, n: u32, value: bool) -> Self {
+                    if value {
+                        self | (1 << n)
+                    } else {
+                        self & !(1 << n)
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned!(u8, u16, u32, u64, u128, usize);
+
+/// How `try_build` handles an existing, non-empty file whose size does not match the capacity of the map.
+/// Empty files are always sized to the capacity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Truncates a longer file to the capacity, failing for a shorter one.
+    Truncate,
+    /// Extends a shorter file to the capacity, failing for a longer one.
+    Extend,
+    /// Fails with `EasyMmapError::FileSizeMismatch`.
+    #[default]
+    Error,
+    /// Maps the file at its current size, as with `capacity_from_file`.
+    UseFileSize,
+}
+
+/// The builder class for the EasyMmap struct.
+/// Provides an easy-to-use interface to create a new EasyMmap struct.
+pub struct EasyMmapBuilder<T> {
+    file: Option<fs::File>,
+    fd: Option<RawFd>,
+    memfd: Option<(String, bool)>,
+    capacity: usize,
+    capacity_bytes: Option<usize>,
+    options: Vec<MapOption>,
+    alignment: Option<usize>,
+    allow_write_execute: bool,
+    trim_on_drop: bool,
+    sync_on_write: bool,
+    anonymous_private: bool,
+    auto_sharing: bool,
+    with_header: bool,
+    capacity_from_file: bool,
+    huge_pages_or_fallback: bool,
+    mismatch_policy: MismatchPolicy,
+    verify_checksum: bool,
+    address: Option<usize>,
+    _type: PhantomData<T>,
+}
+
+impl<'a, T> EasyMmapBuilder<T> {
+    /// Creates a new EasyMmapBuilder struct.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> EasyMmapBuilder<T> {
+        EasyMmapBuilder {
+            file: None,
+            fd: None,
+            memfd: None,
+            capacity: 0,
+            capacity_bytes: None,
+            options: Vec::new(),
+            alignment: None,
+            allow_write_execute: false,
+            trim_on_drop: false,
+            sync_on_write: false,
+            anonymous_private: false,
+            auto_sharing: false,
+            with_header: false,
+            capacity_from_file: false,
+            huge_pages_or_fallback: false,
+            mismatch_policy: MismatchPolicy::Error,
+            verify_checksum: false,
+            address: None,
+            _type: PhantomData,
+        }
+    }
+
+    /// Builds the memory map with the given specifications.
+    /// If the file has been specified, its size will be set to the requirements of the map, see `mismatch_policy`
+    /// for existing files.
+    /// Panics if the map cannot be created, see `try_build` for a non-panicking version.
+    pub fn build(self) -> EasyMmap<'a, T>
+    where
+        T: Copy,
+    {
+        self.try_build().unwrap()
+    }
+
+    /// Checks the specifications of the map without creating it, returning the error that `try_build` would
+    /// fail with before any system call is made.
+    pub fn validate(&self) -> Result<(), EasyMmapError> {
+        let element_size = std::mem::size_of::<T>();
+        if element_size == 0 {
+            return Err(EasyMmapError::ZeroSizedType);
+        }
+
+        let capacity = match self.capacity_bytes {
+            Some(bytes) if bytes % element_size != 0 => {
+                return Err(EasyMmapError::CapacityNotDivisible {
+                    bytes,
+                    element_size,
+                });
+            }
+            Some(bytes) => bytes / element_size,
+            None => self.capacity,
+        };
+        let header = if self.with_header { HEADER_LEN } else { 0 };
+        let bytes = capacity
+            .checked_mul(element_size)
+            .and_then(|bytes| bytes.checked_add(header));
+        if !matches!(bytes, Some(bytes) if bytes <= isize::MAX as usize) {
+            return Err(EasyMmapError::CapacityOverflow(capacity));
+        }
+
+        let flags = MapFlags::from_options(&self.options);
+        if flags.contains(MapFlags::WRITABLE | MapFlags::EXECUTABLE) && !self.allow_write_execute {
+            return Err(EasyMmapError::WriteExecute);
+        }
+
+        // Only one source of file descriptor can be used, unless they are all discarded
+        let descriptors = self.file.is_some() as usize
+            + self.fd.is_some() as usize
+            + self.memfd.is_some() as usize
+            + self
+                .options
+                .iter()
+                .filter(|o| matches!(o, MapOption::MapFd(_)))
+                .count();
+        if descriptors > 1 && !self.anonymous_private {
+            return Err(EasyMmapError::ConflictingFd);
+        }
+
+        Ok(())
+    }
+
+    /// Builds the memory map with the given specifications, returning an error if it cannot be created.
+    pub fn try_build(mut self) -> Result<EasyMmap<'a, T>, EasyMmapError>
+    where
+        T: Copy,
+    {
+        self.validate()?;
+
+        if let Some(bytes) = self.capacity_bytes {
+            self.capacity = bytes / std::mem::size_of::<T>();
+        }
+
+        if self.anonymous_private {
+            self.file = None;
+            self.fd = None;
+            self.memfd = None;
+            self.options.retain(|o| {
+                !matches!(
+                    o,
+                    MapOption::MapFd(_)
+                        | MapOption::MapOffset(_)
+                        | MapOption::MapNonStandardFlags(_)
+                )
+            });
+            self.options.push(MapOption::MapNonStandardFlags(
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+            ));
+        }
+
+        let flags = MapFlags::from_options(&self.options);
+
+        let mut seal = false;
+        if let Some((name, seal_memfd)) = self.memfd.take() {
+            self.file = Some(create_memfd(&name)?);
+            seal = seal_memfd;
+        }
+
+        let data_offset = if self.with_header && self.file.is_some() {
+            HEADER_LEN
+        } else {
+            0
+        };
+
+        if self.file.is_some() {
+            let file = self.file.unwrap();
+
+            // Mapping a file always requires reading it, and shared writable maps require writing it
+            let mode = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+            if mode < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let mode = mode & libc::O_ACCMODE;
+            if mode == libc::O_WRONLY
+                || (flags.contains(MapFlags::WRITABLE) && mode != libc::O_RDWR)
+            {
+                return Err(EasyMmapError::ProtModeMismatch);
+            }
+
+            let element_size = std::mem::size_of::<T>();
+            let file_len = file.metadata()?.len() as usize;
+            let required_len = |capacity: usize| {
+                capacity
+                    .checked_mul(element_size)
+                    .and_then(|len| len.checked_add(data_offset))
+                    .ok_or(EasyMmapError::CapacityOverflow(capacity))
+            };
+            let expected = required_len(self.capacity)?;
+            if file_len > 0 && !self.capacity_from_file && file_len != expected {
+                match self.mismatch_policy {
+                    MismatchPolicy::Truncate if file_len > expected => {}
+                    MismatchPolicy::Extend if file_len < expected => {}
+                    MismatchPolicy::UseFileSize => self.capacity_from_file = true,
+                    _ => {
+                        return Err(EasyMmapError::FileSizeMismatch {
+                            expected,
+                            found: file_len,
+                        })
+                    }
+                }
+            }
+            let mut set_len = true;
+            let mut header_unchanged = false;
+            let mut crc = None;
+            if self.with_header && file_len > 0 {
+                let header = Header::read(&file)?;
+                if header.element_size != element_size {
+                    return Err(EasyMmapError::ElementSizeMismatch {
+                        expected: element_size,
+                        found: header.element_size,
+                    });
+                }
+                // A corrupted count must not overflow the size of the data
+                let data_len = header
+                    .count
+                    .checked_mul(element_size)
+                    .filter(|len| *len <= isize::MAX as usize - HEADER_LEN)
+                    .ok_or(EasyMmapError::InvalidHeader)?;
+                if let (true, Some(expected)) = (self.verify_checksum, header.crc) {
+                    let found = file_crc32(&file, HEADER_LEN, data_len)?;
+                    if found != expected {
+                        return Err(EasyMmapError::ChecksumMismatch { expected, found });
+                    }
+                }
+                if self.capacity_from_file {
+                    self.capacity = header.count;
+                }
+                // The data is left as is only if the count is unchanged
+                if self.capacity == header.count {
+                    crc = header.crc;
+                    header_unchanged = true;
+                }
+            } else if self.capacity_from_file {
+                // Leave any trailing partial element alone
+                self.capacity = file_len / element_size;
+                set_len = false;
+            }
+
+            // allocate enough size in the file, leaving files of the right size untouched so they can be read-only
+            let required = required_len(self.capacity)?;
+            if set_len && required != file_len {
+                file.set_len(required as u64)?;
+            }
+            if self.with_header && !header_unchanged {
+                Header {
+                    element_size,
+                    count: self.capacity,
+                    crc,
+                }
+                .write(&file)?;
+            }
+            if seal {
+                let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+                if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } != 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+            }
+
+            // Get file descriptor of file
+            self.options.push(MapOption::MapFd(file.as_raw_fd()));
+            if self.auto_sharing && !flags.contains(MapFlags::WRITABLE) {
+                self.options
+                    .push(MapOption::MapNonStandardFlags(libc::MAP_PRIVATE));
+            } else {
+                self.options // To make the code share the file in memory
+                    .push(MapOption::MapNonStandardFlags(libc::MAP_SHARED));
+            }
+
+            self.file = Some(file);
+        } else if let Some(fd) = self.fd {
+            self.options.push(MapOption::MapFd(fd));
+            self.options
+                .push(MapOption::MapNonStandardFlags(libc::MAP_SHARED));
+        } else if self.auto_sharing && !self.anonymous_private {
+            let sharing = if flags.contains(MapFlags::WRITABLE) {
+                libc::MAP_SHARED
+            } else {
+                libc::MAP_PRIVATE
+            };
+            self.options
+                .push(MapOption::MapNonStandardFlags(sharing | libc::MAP_ANON));
+        }
+
+        if let Some(addr) = self.address {
+            // `MapAddr` alone implies `MAP_FIXED`, which would silently replace any existing mapping
+            let sharing = self.sharing_flags();
+            self.options.push(MapOption::MapAddr(addr as *const u8));
+            self.options.push(MapOption::MapNonStandardFlags(
+                sharing | libc::MAP_FIXED_NOREPLACE,
+            ));
+        }
+
+        self.options = normalize_options(&self.options);
+
+        let mut huge_pages = false;
+        let mapped = if self.huge_pages_or_fallback {
+            let mut huge_options = self.options.clone();
+            huge_options.push(MapOption::MapNonStandardFlags(
+                self.sharing_flags() | libc::MAP_HUGETLB,
+            ));
+
+            match EasyMmap::new_view(data_offset, self.capacity, &huge_options, None) {
+                Ok(map) => {
+                    huge_pages = true;
+                    Ok(map)
+                }
+                // No huge pages are available, or not for this mapping
+                Err(MapError::ErrNoMem | MapError::ErrUnaligned) => {
+                    EasyMmap::new_view(data_offset, self.capacity, &self.options, None)
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            EasyMmap::new_view(data_offset, self.capacity, &self.options, None)
+        };
+        let mut map = match (mapped, self.address) {
+            (Err(MapError::ErrUnknown(code)), Some(addr)) if code == libc::EEXIST as isize => {
+                return Err(EasyMmapError::AddressUnavailable(addr))
+            }
+            (mapped, _) => mapped?,
+        };
+        // Kernels without `MAP_FIXED_NOREPLACE` only take the address as a hint
+        if let Some(addr) = self.address {
+            if map.region().0 as usize != addr {
+                return Err(EasyMmapError::AddressUnavailable(addr));
+            }
+        }
+        map._file = self.file;
+        map.huge_pages = huge_pages;
+        let map_offset = self
+            .options
+            .iter()
+            .rev()
+            .find_map(|o| match o {
+                MapOption::MapOffset(offset) => Some(*offset),
+                _ => None,
+            })
+            .unwrap_or(0);
+        map.file_offset = (map_offset + data_offset) as u64;
+        map.trim_on_drop = self.trim_on_drop;
+        map.sync_on_write = self.sync_on_write;
+
+        if let Some(alignment) = self.alignment {
+            if map.as_ptr() as usize % alignment != 0 {
+                return Err(EasyMmapError::Misaligned(alignment));
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Returns the sharing flags that mmap would otherwise use, for options that replace them.
+    fn sharing_flags(&self) -> libc::c_int {
+        self.options
+            .iter()
+            .rev()
+            .find_map(|o| match o {
+                MapOption::MapNonStandardFlags(f) => Some(*f),
+                _ => None,
+            })
+            .unwrap_or(if self.file.is_some() {
+                libc::MAP_SHARED
+            } else {
+                libc::MAP_PRIVATE | libc::MAP_ANON
+            })
+    }
+
+    /// Same as `try_build`, but a non-empty file is mapped at its current size instead of being resized
+    /// to `capacity`, which is then only used for new files. See `capacity_from_file`.
+    pub fn build_or_open(mut self) -> Result<EasyMmap<'a, T>, EasyMmapError>
+    where
+        T: Copy,
+    {
+        if let Some(file) = &self.file {
+            if file.metadata()?.len() > 0 {
+                self.capacity_from_file = true;
+            }
+        }
+        self.try_build()
+    }
+
+    /// Builds an anonymous read-write map holding a copy of the contents of the file at `path`.
+    /// The capacity is derived from the file size. The file is not kept open, so writes to the map never reach it.
+    pub fn load_file_copy(mut self, path: impl AsRef<Path>) -> Result<EasyMmap<'a, T>, EasyMmapError>
+    where
+        T: Copy + FileSafe,
+    {
+        let bytes = fs::read(path)?;
+        self.file = None;
+
+        let map = self
+            .capacity(bytes.len() / std::mem::size_of::<T>().max(1))
+            .readable()
+            .writable()
+            .try_build()?;
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                map._data.as_mut_ptr().cast::<u8>(),
+                map.len_bytes(),
+            )
+        };
+
+        Ok(map)
+    }
+
+    /// Builds the map filled with `f` and already turned read-only, see `EasyMmap::seal`, so no writable
+    /// handle to it is ever exposed.
+    pub fn seal_after(self, f: impl Fn(usize) -> T) -> Result<EasyMmap<'a, T>, EasyMmapError>
+    where
+        T: Copy,
+    {
+        Ok(self.readable().writable().try_build()?.seal(f)?)
+    }
+
+    /// Builds a read-write map holding the items of `iter`.
+    /// The capacity is taken from the upper bound of the iterator's size hint, or from the number of items
+    /// if there is no upper bound.
+    /// An empty iterator gives an empty map.
+    pub fn collect_into<I: IntoIterator<Item = T>>(self, iter: I) -> EasyMmap<'a, T>
+    where
+        T: Copy,
+    {
+        let iter = iter.into_iter();
+        match iter.size_hint() {
+            (_, Some(upper)) => {
+                let mut map = self.capacity(upper).readable().writable().build();
+                let mut count = 0;
+                for (slot, value) in map._data.iter_mut().zip(iter) {
+                    *slot = value;
+                    count += 1;
+                }
+                map.mark_dirty();
+                map.truncate(count);
+                map
+            }
+            (_, None) => {
+                let values = iter.collect::<Vec<_>>();
+                let mut map = self.capacity(values.len()).readable().writable().build();
+                map.write_at(0, &values);
+                map
+            }
+        }
+    }
+
+    /// Passes the ownership of the file to the memory map.
+    pub fn file(mut self, file: fs::File) -> EasyMmapBuilder<T>
+    where
+        T: FileSafe,
+    {
+        self.file = Some(file);
+        self
+    }
+
+    /// Maps the already open descriptor `fd`, e.g. a socket, memfd or inherited handle, as a shared map.
+    /// Unlike `file`, the descriptor is not owned by the map and is not resized: it must already hold
+    /// `capacity` elements.
+    pub fn fd(mut self, fd: RawFd) -> EasyMmapBuilder<T>
+    where
+        T: FileSafe,
+    {
+        self.fd = Some(fd);
+        self
+    }
+
+    /// Maps a new anonymous file created with `memfd_create`, shared with any process it is passed to.
+    /// With `seal`, the file is sealed against shrinking and growing once sized, so the other processes
+    /// can safely rely on its size.
+    pub fn memfd(mut self, name: &str, seal: bool) -> EasyMmapBuilder<T>
+    where
+        T: FileSafe,
+    {
+        self.memfd = Some((name.to_string(), seal));
+        self
+    }
+
+    /// Sets the capacity that the mapped region must have.
+    /// This capacity must be the number of objects of type `T` that can be stored in the memory map.
+    /// A capacity of zero gives an empty map.
+    pub fn capacity(mut self, capacity: usize) -> EasyMmapBuilder<T> {
+        self.capacity = capacity;
+        self.capacity_bytes = None;
+        self
+    }
+
+    /// Sets the capacity of the mapped region in bytes instead of elements.
+    /// The number of bytes must be a multiple of the size of `T`.
+    pub fn capacity_bytes(mut self, bytes: usize) -> EasyMmapBuilder<T> {
+        self.capacity_bytes = Some(bytes);
+        self
+    }
+
+    /// Batch sets the options that the mapped region must have.
+    /// As the options may map a descriptor with `MapFd`, `T` must be `FileSafe` like for `file`.
+    pub fn options(mut self, options: &[MapOption]) -> EasyMmapBuilder<T>
+    where
+        T: FileSafe,
+    {
+        self.options = options.to_vec();
+        self
+    }
+
+    /// Adds an individual option.
+    /// As the option may map a descriptor with `MapFd`, `T` must be `FileSafe` like for `file`.
+    pub fn add_option(mut self, option: MapOption) -> EasyMmapBuilder<T>
+    where
+        T: FileSafe,
+    {
+        self.options.push(option);
+        self
+    }
+
+    pub fn readable(mut self) -> EasyMmapBuilder<T> {
+        self.options.push(MapOption::MapReadable);
+        self
+    }
+
+    pub fn writable(mut self) -> EasyMmapBuilder<T> {
+        self.options.push(MapOption::MapWritable);
+        self
+    }
+
+    /// Requires the data pointer of the map to be aligned to `align` bytes, e.g. for SIMD access.
+    /// Page-aligned maps always satisfy this, but an offset into a file may not.
+    pub fn alignment(mut self, align: usize) -> EasyMmapBuilder<T> {
+        assert!(align.is_power_of_two(), "Alignment {} is not a power of two", align);
+        self.alignment = Some(align);
+        self
+    }
+
+    /// Trims the backing file down to the length of the map when it is dropped, e.g. after a `truncate`.
+    pub fn trim_on_drop(mut self) -> EasyMmapBuilder<T> {
+        self.trim_on_drop = true;
+        self
+    }
+
+    /// Schedules the write back of the touched pages to the file after every indexed write and `write_at`,
+    /// using `MS_ASYNC`. The element returned by `index_mut` is scheduled once its new value is in place,
+    /// on the next write or flush of the map, or when it is dropped; `replace` schedules it right away.
+    /// This adds a system call to every write, which is costly for maps updated in tight loops.
+    pub fn sync_on_write(mut self) -> EasyMmapBuilder<T> {
+        self.sync_on_write = true;
+        self
+    }
+
+    /// Guarantees the map is process-local memory: no file and no sharing, even if requested by other options.
+    /// Any file given to the builder is discarded.
+    pub fn anonymous_private(mut self) -> EasyMmapBuilder<T> {
+        self.anonymous_private = true;
+        self
+    }
+
+    /// Stores a small header with the element size and count at the start of the file, with the data
+    /// placed after it. Reopening the file with a header written for a different element size fails.
+    pub fn with_header(mut self) -> EasyMmapBuilder<T> {
+        self.with_header = true;
+        self
+    }
+
+    /// When opening a file `with_header` holding a CRC32 of its data, see `EasyMmap::write_checksum`, checks
+    /// the data against it and fails with `EasyMmapError::ChecksumMismatch` if they differ.
+    pub fn verify_checksum(mut self) -> EasyMmapBuilder<T> {
+        self.verify_checksum = true;
+        self
+    }
+
+    /// Maps the region at the page-aligned virtual address `addr`, e.g. for structures holding pointers into
+    /// the map that are shared between processes. Fails with `EasyMmapError::AddressUnavailable` if the
+    /// address is already in use or the kernel doesn't honor it.
+    pub fn at_address(mut self, addr: usize) -> EasyMmapBuilder<T> {
+        self.address = Some(addr);
+        self
+    }
+
+    /// Sets how an existing file whose size does not match the capacity is handled, failing by default.
+    pub fn mismatch_policy(mut self, policy: MismatchPolicy) -> EasyMmapBuilder<T> {
+        self.mismatch_policy = policy;
+        self
+    }
+
+    /// Takes the capacity from the file instead of `capacity`, either from its header when built
+    /// `with_header` or from its length otherwise.
+    pub fn capacity_from_file(mut self) -> EasyMmapBuilder<T> {
+        self.capacity_from_file = true;
+        self
+    }
+
+    /// Maps the region shared if it is writable, and private otherwise.
+    /// This prevents writes through a map meant to be read-only from being persisted, while writable maps
+    /// don't pay for copy-on-write.
+    pub fn auto_sharing(mut self) -> EasyMmapBuilder<T> {
+        self.auto_sharing = true;
+        self
+    }
+
+    /// Backs the map with huge pages if possible, and with normal pages otherwise, e.g. when there is no
+    /// huge page pool. See `EasyMmap::uses_huge_pages` for the outcome.
+    pub fn huge_pages_or_fallback(mut self) -> EasyMmapBuilder<T> {
+        self.huge_pages_or_fallback = true;
+        self
+    }
+
+    /// Allows the map to be both writable and executable, which is rejected by default.
+    pub fn allow_write_execute(mut self) -> EasyMmapBuilder<T> {
+        self.allow_write_execute = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_random_file() -> fs::File {
+        fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(format!("/tmp/map{}", rand::random::<u64>()))
+            .unwrap()
+    }
+
+    fn create_random_file_with_path() -> (String, fs::File) {
+        let path = format!("/tmp/map{}", rand::random::<u64>());
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    #[test]
+    fn map_create() {
+        let map = &mut EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .options(&[])
+            .build();
+
+        assert_eq!(map.len(), 10);
+    }
+
+    #[test]
+    fn map_write_read() {
+        let map = &mut EasyMmapBuilder::<u32>::new()
+            .capacity(1)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        map[0] = 1;
+
+        assert_eq!(map[0], 1);
+    }
+
+    #[test]
+    #[allow(clippy::map_clone)]
+    fn map_iter() {
+        let map = &mut EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        for i in 0..5 {
+            map[i] = i as u32;
+        }
+
+        assert_eq!(
+            map.iter().map(|x| *x).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn map_oob_write() {
+        let map = &mut EasyMmapBuilder::<u32>::new()
+            .capacity(1)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        map[1] = 1;
+    }
+
+    #[test]
+    #[should_panic]
+    #[allow(clippy::no_effect)]
+    fn map_oob_read() {
+        let map = &mut EasyMmapBuilder::<u32>::new()
+            .capacity(1)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        map[1];
+    }
+
+    #[test]
+    fn map_create_file() {
+        let file = create_random_file();
+
+        let map = &mut EasyMmapBuilder::<u32>::new()
+            .file(file)
+            .capacity(10)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        assert_eq!(map.len(), 10);
+
+        // Write to file
+        map[0] = 1;
+        assert_eq!(map[0], 1);
+    }
+
+    #[test]
+    fn test_large_size() {
+        let map = &mut EasyMmapBuilder::new()
+            .capacity(65535)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        // Populate map
+        for i in 0..65535 {
+            map[i] = i as u64;
+        }
+
+        // Check if map is populated
+        for i in 0..65535 {
+            assert_eq!(map[i], i as u64);
+        }
+    }
+
+    // A padded struct with a `bool` is exactly what the `pod` feature rejects for file-backed maps
+    #[test]
+    #[cfg(not(feature = "pod"))]
+    fn test_struct() {
+        #[derive(Clone, Copy)]
+        struct TestStruct {
+            v1: i64,
+            v2: bool,
+        }
+
+        let length = 100000;
+
+        let file = create_random_file();
+
+        let map = &mut EasyMmapBuilder::new()
+            .capacity(length)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .file(file)
+            .build();
+
+        for i in 0..length {
+            map[i] = TestStruct {
+                v1: i as i64,
+                v2: i % 2 == 0,
+            };
+        }
+
+        for i in 0..length {
+            let s = map[i];
+            assert_eq!(s.v1, i as i64);
+            assert_eq!(s.v2, i % 2 == 0);
+        }
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut map = EasyMmapBuilder::<i32>::new()
+            .capacity(5)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        for i in 0..5 {
+            map[i] = i as i32;
+        }
+
+        for (i, x) in map.iter().enumerate() {
+            assert_eq!(i as i32, *x);
+        }
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut map = EasyMmapBuilder::<i32>::new()
+            .capacity(5)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        for (i, x) in map.iter_mut().enumerate() {
+            *x = i as i32;
+        }
+
+        for (i, x) in map.iter().enumerate() {
+            assert_eq!(i as i32, *x);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::map_clone)]
+    fn test_complex_iterator() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        map.iter_mut()
+            .enumerate()
+            .for_each(|(idx, x)| *x = idx as u32);
+
+        let v = map
+            .iter()
+            .map(|x| *x * 3)
+            .filter(|x| x % 2 == 0)
+            .collect::<Vec<u32>>();
+
+        map.iter_mut().zip(v).for_each(|(x, y)| *x = y);
+
+        assert_eq!(
+            map.iter().map(|x| *x).collect::<Vec<_>>(),
+            vec![0, 6, 12, 3, 4]
+        );
+    }
+
+    #[test]
+    fn get_data_slice() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        map.iter_mut()
+            .enumerate()
+            .for_each(|(idx, x)| *x = idx as u32);
+
+        let slice = map.get_data_as_slice();
+
+        assert_eq!(slice.len(), 5);
+        assert_eq!(slice[0], map[0]);
+        assert_eq!(slice[1], map[1]);
+        assert_eq!(slice[2], map[2]);
+        assert_eq!(slice[3], map[3]);
+        assert_eq!(slice[4], map[4]);
+
+        let slice = map.get_data_as_slice_mut();
+        assert_eq!(slice.len(), 5);
+        slice[0] = 10;
+
+        assert_eq!(map[0], 10);
+    }
+
+    #[test]
+    fn easier_builder() {
+        let mut map = EasyMmapBuilder::<i32>::new()
+            .capacity(1)
+            .readable()
+            .writable()
+            .build();
+
+        map[0] = 1;
+        assert_eq!(map[0], 1);
+    }
+
+    #[test]
+    fn fill_constant() {
+        let mut map = EasyMmapBuilder::<i32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|_| 1);
+        assert_eq!(map.get_data_as_slice(), vec![1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn fill_large() {
+        let mut map = EasyMmapBuilder::<i32>::new()
+            .capacity(100000)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as i32);
+        assert_eq!(map.get_data_as_slice(), (0..100000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn open_written_file() {
+        let values = vec![1, 2, 3, 4, 5, 10, 20, 50];
+
+        // Write to random file
+        let filename = format!("/tmp/file{}", rand::random::<i32>());
+        fs::write(&filename, &values).expect("Failed to write values to file");
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(false)
+            .open(&filename)
+            .expect("Failed to open file");
+
+        // Now read the contents into the mmap
+        let map = EasyMmapBuilder::<u8>::new()
+            .capacity(values.len())
+            .writable()
+            .readable()
+            .file(file)
+            .build();
+
+        assert_eq!(map.get_data_as_slice(), values);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn parallel_iterators() {
+        let mut map = EasyMmapBuilder::<i32>::new()
+            .capacity(5)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        map.fill(|i| i as i32);
+
+        assert_eq!(
+            map.par_iter().map(|x| *x).collect::<Vec<_>>(),
+            (0..5).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn parallel_iterators_mut() {
+        let mut map = EasyMmapBuilder::<i32>::new()
+            .capacity(5)
+            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .build();
+
+        map.fill(|i| i as i32);
+
+        map.par_iter_mut().for_each(|x| *x += 1);
+
+        assert_eq!(
+            map.par_iter().map(|x| *x).collect::<Vec<_>>(),
+            (1..6).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reverse() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+        map.reverse();
+
+        assert_eq!(map.get_data_as_slice(), &[4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn reverse_file() {
+        let (filename, file) = create_random_file_with_path();
+
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+
+        map.fill(|i| i as u8);
+        map.reverse();
+
+        assert_eq!(fs::read(&filename).unwrap(), vec![4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn options_introspection() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(1)
+            .readable()
+            .writable()
+            .build();
+
+        assert!(map.options().contains(MapFlags::READABLE | MapFlags::WRITABLE));
+        assert!(!map.options().contains(MapFlags::EXECUTABLE));
+        assert!(!map.options().contains(MapFlags::SHARED));
+    }
+
+    #[test]
+    fn options_introspection_file() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(1)
+            .readable()
+            .file(create_random_file())
+            .build();
+
+        assert!(map.options().contains(MapFlags::READABLE | MapFlags::SHARED));
+        assert!(!map.options().contains(MapFlags::WRITABLE));
+    }
+
+    #[test]
+    fn fill_bytes() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill_bytes(0xFF);
+        assert!(map.iter().all(|x| *x == u32::MAX));
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_for_each_chunk() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(10000)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+
+        let total = AtomicUsize::new(0);
+        map.par_for_each_chunk(
+            128,
+            || 0usize,
+            |count, chunk| {
+                *count = chunk.iter().filter(|x| *x % 3 == 0).count();
+                total.fetch_add(*count, Ordering::Relaxed);
+            },
+        );
+
+        assert_eq!(total.into_inner(), map.iter().filter(|x| *x % 3 == 0).count());
+    }
+
+    #[test]
+    fn split_first_last() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+
+        let (head, tail) = map.split_first().unwrap();
+        assert_eq!(*head, 0);
+        assert_eq!(tail, &[1, 2, 3, 4]);
+
+        let (last, rest) = map.split_last().unwrap();
+        assert_eq!(*last, 4);
+        assert_eq!(rest, &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn alignment() {
+        let map = EasyMmapBuilder::<u8>::new()
+            .capacity(100)
+            .readable()
+            .writable()
+            .alignment(64)
+            .try_build()
+            .unwrap();
+
+        assert_eq!(map.as_ptr() as usize % 64, 0);
+    }
+
+    #[test]
+    fn step_by_iter() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+
+        assert_eq!(
+            map.step_by_iter(2).copied().collect::<Vec<_>>(),
+            vec![0, 2, 4, 6, 8]
+        );
+    }
+
+    fn read_only_map() -> EasyMmap<'static, u32> {
+        EasyMmapBuilder::<u32>::new().capacity(5).readable().build()
+    }
+
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn read_only_index_mut() {
+        read_only_map()[0] = 1;
+    }
+
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn read_only_iter_mut() {
+        let _ = read_only_map().iter_mut();
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    #[should_panic(expected = "not writable")]
+    fn read_only_par_iter_mut() {
+        let _ = read_only_map().par_iter_mut();
+    }
+
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn read_only_slice_mut() {
+        read_only_map().get_data_as_slice_mut();
+    }
+
+    #[test]
+    fn write_execute_rejected() {
+        let result = EasyMmapBuilder::<u8>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .add_option(MapOption::MapExecutable)
+            .try_build();
+
+        assert!(matches!(result, Err(EasyMmapError::WriteExecute)));
+    }
+
+    #[test]
+    fn write_execute_allowed() {
+        let map = EasyMmapBuilder::<u8>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .add_option(MapOption::MapExecutable)
+            .allow_write_execute()
+            .try_build()
+            .unwrap();
+
+        assert!(map.options().contains(MapFlags::WRITABLE | MapFlags::EXECUTABLE));
+    }
+
+    #[test]
+    fn concat() {
+        let mut first = EasyMmapBuilder::<u32>::new()
+            .capacity(3)
+            .readable()
+            .writable()
+            .build();
+        let mut second = EasyMmapBuilder::<u32>::new()
+            .capacity(2)
+            .readable()
+            .writable()
+            .build();
+
+        first.fill(|i| i as u32);
+        second.fill(|i| i as u32 + 3);
+
+        let map = first.concat(&second);
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn try_index() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+        assert_eq!(map.try_index(4), Ok(&4));
+
+        let err = map.try_index(map.len()).unwrap_err();
+        assert_eq!(err, OutOfBounds { index: 5, len: 5 });
+
+        let message = err.to_string();
+        assert!(message.contains("Index 5"));
+        assert!(message.contains("length 5"));
+    }
+
+    #[test]
+    fn prefetch_cacheline() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(1024)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u64);
+        map.prefetch_cacheline(0);
+        map.prefetch_cacheline(1023);
+
+        assert_eq!(map[1023], 1023);
+    }
+
+    #[test]
+    #[should_panic]
+    fn prefetch_cacheline_oob() {
+        let map = EasyMmapBuilder::<u64>::new()
+            .capacity(1)
+            .readable()
+            .build();
+
+        map.prefetch_cacheline(1);
+    }
+
+    #[derive(Clone, Copy)]
+    struct Zst;
+
+    #[test]
+    fn zero_sized_type() {
+        let result = EasyMmapBuilder::<Zst>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .try_build();
+
+        assert!(matches!(result, Err(EasyMmapError::ZeroSizedType)));
+    }
+
+    #[test]
+    #[should_panic(expected = "ZeroSizedType")]
+    fn zero_sized_type_build() {
+        EasyMmapBuilder::<Zst>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .build();
+    }
+
+    #[test]
+    fn copy_to_file() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32 * 10);
+
+        let filename = format!("/tmp/file{}", rand::random::<u64>());
+        map.copy_to_file(&filename).unwrap();
+
+        let contents = fs::read(&filename).unwrap();
+        assert_eq!(contents.len(), map.len_bytes());
+        assert_eq!(contents, map.as_bytes());
+    }
+
+    #[test]
+    fn load_file_copy() {
+        let values = vec![1u8, 2, 3, 4, 5];
+        let filename = format!("/tmp/file{}", rand::random::<u64>());
+        fs::write(&filename, &values).unwrap();
+
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .load_file_copy(&filename)
+            .unwrap();
+
+        assert_eq!(map.len(), values.len());
+        assert_eq!(map.get_data_as_slice(), values);
+
+        map.fill(|_| 0);
+        assert_eq!(fs::read(&filename).unwrap(), values);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn fill_range_parallel() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(100000)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill_range_parallel(0..50000, |i| i as u32 + 1);
+
+        assert!(map.iter().take(50000).enumerate().all(|(i, x)| *x == i as u32 + 1));
+        assert!(map.iter().skip(50000).all(|x| *x == 0));
+    }
+
+    #[test]
+    fn resize() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+        map.resize(10).unwrap();
+
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 3, 4, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn resize_to_zero_keeps_file() {
+        let (path, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+        map.fill(|i| i as u32);
+
+        assert_eq!(map.resize(0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 20);
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn try_resize_shrink() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .file(create_random_file())
+            .build();
+
+        map.fill(|i| i as u32);
+
+        assert!(map.try_resize(3, false).is_err());
+        assert_eq!(map.len(), 5);
+
+        map.try_resize(3, true).unwrap();
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn iter_byte_offsets() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+
+        assert_eq!(
+            map.iter_byte_offsets()
+                .map(|(offset, v)| (offset, *v))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (4, 1), (8, 2), (12, 3)]
+        );
+    }
+
+    #[test]
+    fn conflicting_fd() {
+        let other = create_random_file();
+
+        let result = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .add_option(MapOption::MapFd(other.as_raw_fd()))
+            .file(create_random_file())
+            .try_build();
+
+        assert!(matches!(result, Err(EasyMmapError::ConflictingFd)));
+    }
+
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn read_only_fill_bytes() {
+        read_only_map().fill_bytes(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn read_only_reverse() {
+        read_only_map().reverse();
+    }
+
+    #[test]
+    fn read_at() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+
+        let mut buf = [0; 5];
+        map.read_at(2, &mut buf[..3]);
+        assert_eq!(buf, [2, 3, 4, 0, 0]);
+    }
+
+    #[test]
+    fn write_at() {
+        let (filename, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+
+        map.write_at(3, &[7, 8]);
+        assert_eq!(map.get_data_as_slice(), &[0, 0, 0, 7, 8]);
+        assert_eq!(fs::read(&filename).unwrap(), vec![0, 0, 0, 7, 8]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn write_at_oob() {
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.write_at(4, &[1, 2]);
+    }
+
+    #[test]
+    fn capacity_bytes() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity_bytes(40)
+            .readable()
+            .writable()
+            .build();
+
+        assert_eq!(map.len(), 10);
+
+        let result = EasyMmapBuilder::<u32>::new()
+            .capacity_bytes(41)
+            .readable()
+            .try_build();
+        assert!(matches!(
+            result,
+            Err(EasyMmapError::CapacityNotDivisible { bytes: 41, .. })
+        ));
+    }
+
+    #[test]
+    fn try_fill() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        let result = map.try_fill(|i| if i == 3 { Err("bad value") } else { Ok(i as u32 + 1) });
+
+        assert_eq!(result, Err("bad value"));
+        assert_eq!(map.get_data_as_slice(), &[1, 2, 3, 0, 0]);
+    }
+
+    #[test]
+    fn trim_on_drop() {
+        let (filename, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .file(file)
+            .trim_on_drop()
+            .build();
+
+        map.fill(|i| i as u32);
+        map.truncate(4);
+        assert_eq!(map.len(), 4);
+        drop(map);
+
+        let contents = fs::read(&filename).unwrap();
+        assert_eq!(contents.len(), 4 * std::mem::size_of::<u32>());
+        assert_eq!(contents[4..8], 1u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn map_region() {
+        let (filename, file) = create_random_file_with_path();
+        let values = (0..16u32).collect::<Vec<_>>();
+        let bytes = values.iter().flat_map(|v| v.to_ne_bytes()).collect::<Vec<_>>();
+        fs::write(&filename, &bytes).unwrap();
+
+        let mut map = super::map_region::<u32>(file, 4, 4).unwrap();
+        assert_eq!(map.get_data_as_slice(), &[4, 5, 6, 7]);
+
+        map[0] = 40;
+        let contents = fs::read(&filename).unwrap();
+        assert_eq!(contents[16..20], 40u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn map_region_overflow() {
+        let file = create_random_file();
+        file.set_len(64).unwrap();
+
+        let result = super::map_region::<u32>(file.try_clone().unwrap(), usize::MAX / 2, 1);
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        // Wraps around to an end of 20 bytes without the checks
+        let result = super::map_region::<u32>(file, 4, usize::MAX / 4 + 2);
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn is_dirty() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .file(create_random_file())
+            .build();
+
+        assert!(!map.is_dirty());
+
+        map[0] = 1;
+        assert!(map.is_dirty());
+
+        map.flush().unwrap();
+        assert!(!map.is_dirty());
+
+        let _ = map[0];
+        assert!(!map.is_dirty());
+    }
+
+    #[test]
+    fn dirty_transitions() {
+        let (filename, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+
+        map.fill(|i| i as u8);
+        assert!(map.is_dirty());
+        map.flush().unwrap();
+        assert!(!map.is_dirty());
+
+        map.get_data_as_slice_mut()[0] = 10;
+        assert!(map.is_dirty());
+        map.iter_mut().for_each(|x| *x += 1);
+        assert!(map.is_dirty());
+
+        map.close().unwrap();
+        assert_eq!(fs::read(&filename).unwrap(), vec![11, 2, 3, 4]);
+    }
+
+    #[test]
+    fn endianness() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(2)
+            .readable()
+            .writable()
+            .build();
+
+        map[0] = 1;
+        map[1] = 0x01020304;
+
+        map.to_big_endian();
+        assert_eq!(map.as_bytes(), &[0, 0, 0, 1, 1, 2, 3, 4]);
+        assert_eq!(u32::from_be(map[1]), 0x01020304);
+
+        map.fill(|i| i as u32 + 1);
+        map.to_little_endian();
+        assert_eq!(map.as_bytes(), &[1, 0, 0, 0, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn partition_in_place() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+        let pivot = map.partition_in_place(|x| x % 2 == 0);
+
+        assert_eq!(pivot, 5);
+        assert!(map.iter().take(pivot).all(|x| x % 2 == 0));
+        assert!(map.iter().skip(pivot).all(|x| x % 2 == 1));
+    }
+
+    #[test]
+    fn resize_file_keeps_options() {
+        let (filename, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+
+        map.fill(|i| i as u8);
+        map.resize(6).unwrap();
+
+        assert!(map.options().contains(MapFlags::WRITABLE | MapFlags::SHARED));
+        map[5] = 5;
+        assert_eq!(fs::read(&filename).unwrap(), vec![0, 1, 2, 3, 0, 5]);
+    }
+
+    #[test]
+    fn count_set_bits() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .build();
+
+        assert_eq!(map.count_set_bits(), 0);
+
+        map[0] = 0b1011;
+        map[3] = u64::MAX;
+        assert_eq!(map.count_set_bits(), 3 + 64);
+    }
+
+    #[test]
+    fn bit_operations() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(2)
+            .readable()
+            .writable()
+            .build();
+
+        map.bit_set(70);
+        assert!(map.bit_get(70));
+        assert_eq!(map[1], 1 << 6);
+        assert_eq!(map.count_set_bits(), 1);
+
+        map.bit_clear(70);
+        assert!(!map.bit_get(70));
+        assert_eq!(map[1], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn bit_oob() {
+        let map = EasyMmapBuilder::<u64>::new().capacity(2).readable().build();
+        map.bit_get(128);
+    }
+
+    #[test]
+    #[cfg(feature = "rand")]
+    fn fill_random_bytes() {
+        let build = || {
+            EasyMmapBuilder::<u64>::new()
+                .capacity(100)
+                .readable()
+                .writable()
+                .build()
+        };
+        let (mut first, mut second, mut third) = (build(), build(), build());
+
+        first.fill_random_bytes(42);
+        second.fill_random_bytes(42);
+        third.fill_random_bytes(43);
+
+        assert_eq!(first.as_bytes(), second.as_bytes());
+        assert_ne!(first.as_bytes(), third.as_bytes());
+    }
+
+    #[test]
+    fn collect_into() {
+        let map = EasyMmapBuilder::<u32>::new().collect_into((0..5).map(|x| x * x));
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 4, 9, 16]);
+
+        let map = EasyMmapBuilder::<u32>::new().collect_into((0..10).filter(|x| x % 3 == 0));
+        assert_eq!(map.get_data_as_slice(), &[0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn collect_into_empty() {
+        let map = EasyMmapBuilder::<u32>::new().collect_into(std::iter::empty());
+        assert_eq!(map.len(), 0);
+
+        let unbounded = std::iter::repeat(1).take_while(|_| false);
+        let map = EasyMmapBuilder::<u32>::new().collect_into(unbounded);
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn get_slice() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+
+        assert_eq!(map.get_slice(1..3), Some(&[1, 2][..]));
+        assert_eq!(map.get_slice(3..6), None);
+
+        map.get_slice_mut(3..5).unwrap().fill(9);
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 9, 9]);
+        assert!(map.get_slice_mut(5..6).is_none());
+    }
+
+    #[test]
+    fn residency() {
+        let map = EasyMmapBuilder::<u64>::new()
+            .capacity(100000)
+            .readable()
+            .writable()
+            .file(create_random_file())
+            .build();
+
+        map.prefault_all();
+        assert!(map.residency().unwrap() > 0.99);
+    }
+
+    #[test]
+    fn shared_atomic_u64() {
+        use std::sync::atomic::Ordering;
+
+        let mut map = super::shared_atomic_u64(41);
+
+        unsafe {
+            let pid = libc::fork();
+            assert!(pid >= 0);
+            if pid == 0 {
+                map.as_atomic()[0].fetch_add(1, Ordering::SeqCst);
+                libc::_exit(0);
+            }
+
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+        }
+
+        assert_eq!(map.as_atomic()[0].load(Ordering::SeqCst), 42);
+    }
+
+    #[test]
+    fn anonymous_private() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .add_option(MapOption::MapNonStandardFlags(
+                libc::MAP_SHARED | libc::MAP_ANON,
+            ))
+            .anonymous_private()
+            .build();
+
+        assert!(!map.is_file_backed());
+        assert!(!map.options().contains(MapFlags::SHARED));
+
+        map.fill(|i| i as u32);
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn auto_sharing_writable() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .file(create_random_file())
+            .auto_sharing()
+            .build();
+
+        assert!(map.options().contains(MapFlags::SHARED));
+    }
+
+    #[test]
+    fn auto_sharing_read_only() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .file(create_random_file())
+            .auto_sharing()
+            .build();
+
+        assert!(!map.options().contains(MapFlags::SHARED));
+
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .auto_sharing()
+            .build();
+
+        assert!(!map.options().contains(MapFlags::SHARED));
+    }
+
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn read_only_fill() {
+        read_only_map().fill(|i| i as u32);
+    }
+
+    #[test]
+    fn try_fill_checked() {
+        let result = read_only_map().try_fill_checked(|i| i as u32);
+        assert!(matches!(result, Err(EasyMmapError::NotWritable)));
+
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(3)
+            .readable()
+            .writable()
+            .build();
+        map.try_fill_checked(|i| i as u32).unwrap();
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let (path, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .with_header()
+            .build();
+        map.fill(|i| i as u32 * 3);
+        drop(map);
+
+        let open = || {
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap()
+        };
+        let map = EasyMmapBuilder::<u32>::new()
+            .readable()
+            .file(open())
+            .with_header()
+            .capacity_from_file()
+            .build();
+        assert_eq!(map.get_data_as_slice(), &[0, 3, 6, 9]);
+        drop(map);
+
+        let result = EasyMmapBuilder::<u64>::new()
+            .readable()
+            .file(open())
+            .with_header()
+            .capacity_from_file()
+            .try_build();
+        assert!(matches!(
+            result,
+            Err(EasyMmapError::ElementSizeMismatch {
+                expected: 8,
+                found: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn header_count_overflow() {
+        let (path, file) = create_random_file_with_path();
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .with_header()
+            .build();
+        drop(map);
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        file.write_all_at(&u64::MAX.to_le_bytes(), 24).unwrap();
+        let result = EasyMmapBuilder::<u32>::new()
+            .readable()
+            .file(file)
+            .with_header()
+            .capacity_from_file()
+            .verify_checksum()
+            .try_build();
+        assert!(matches!(result, Err(EasyMmapError::InvalidHeader)));
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_iter_min_len_sum() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(10_000)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u64);
+
+        let parallel: u64 = map.par_iter_min_len(1024).sum();
+        assert_eq!(parallel, map.iter().sum::<u64>());
+    }
+
+    #[test]
+    fn compare_exchange_element() {
+        let mut map = super::shared_atomic_u64(5);
+        assert_eq!(map.compare_exchange(0, 5, 6), Ok(5));
+        assert_eq!(map.compare_exchange(0, 5, 7), Err(6));
+        assert_eq!(map[0], 6);
+        map[0] = 1;
+    }
+
+    #[test]
+    fn fill_sequential_from_start() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+        map.fill_sequential(100);
+        assert_eq!(map.get_data_as_slice(), &[100, 101, 102, 103, 104]);
+    }
+
+    #[test]
+    fn dedup_compact_sorted() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(7)
+            .readable()
+            .writable()
+            .build();
+        map.write_at(0, &[1, 1, 2, 3, 3, 3, 4]);
+
+        let len = map.dedup_compact();
+        assert_eq!(len, 4);
+        assert_eq!(&map.get_data_as_slice()[..len], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn lock_partial_range() {
+        let map = EasyMmapBuilder::<u64>::new()
+            .capacity(100_000)
+            .readable()
+            .writable()
+            .build();
+
+        match map.lock_range(0..10) {
+            Ok(()) => map.unlock_range(0..10).unwrap(),
+            // Locking memory may not be allowed in the test environment
+            Err(e) => assert!(matches!(
+                e.raw_os_error(),
+                Some(libc::EPERM) | Some(libc::ENOMEM)
+            )),
+        }
+    }
+
+    #[test]
+    fn build_or_open_existing_file() {
+        let (path, file) = create_random_file_with_path();
+        fs::write(&path, [1u8, 2, 3, 4, 5, 6]).unwrap();
+
+        let map = EasyMmapBuilder::<u16>::new()
+            .readable()
+            .writable()
+            .file(file)
+            .build_or_open()
+            .unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.as_bytes(), &[1, 2, 3, 4, 5, 6]);
+        drop(map);
+        assert_eq!(fs::read(&path).unwrap(), [1, 2, 3, 4, 5, 6]);
+
+        let (_, file) = create_random_file_with_path();
+        let map = EasyMmapBuilder::<u16>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .build_or_open()
+            .unwrap();
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn validate_against_short_file() {
+        let (path, file) = create_random_file_with_path();
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+        assert!(map.validate_against_file().unwrap());
+
+        // Another writer leaves the file shorter than the map
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(6)
+            .unwrap();
+        assert!(!map.validate_against_file().unwrap());
+    }
+
+    #[test]
+    fn entry_or_insert_default() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .build();
+        *map.entry(1) = 7;
+
+        assert_eq!(*map.entry_or_insert(0, 42), 42);
+        assert_eq!(*map.entry_or_insert(1, 42), 7);
+        *map.entry_or_insert(0, 1) += 1;
+        assert_eq!(map.get_data_as_slice(), &[43, 7, 0, 0]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "rayon"))]
+    fn serial_api_without_rayon() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32);
+        map.iter_mut().for_each(|x| *x += 1);
+        assert_eq!(map.iter().sum::<u32>(), 15);
+    }
+
+    #[test]
+    fn transmute_elements_to_struct() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[repr(C)]
+        struct Pair {
+            a: u32,
+            b: u32,
+        }
+
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(16)
+            .readable()
+            .writable()
+            .build();
+        map.write_at(0, &1u32.to_ne_bytes());
+        map.write_at(4, &2u32.to_ne_bytes());
+        map.write_at(8, &3u32.to_ne_bytes());
+        map.write_at(12, &4u32.to_ne_bytes());
+
+        // Any bit pattern is a valid `Pair`
+        let pairs = unsafe { map.transmute_elements::<Pair>() };
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0], Pair { a: 1, b: 2 });
+        assert_eq!(pairs[1], Pair { a: 3, b: 4 });
+    }
+
+    #[test]
+    #[cfg(feature = "rand")]
+    fn shuffle_seeded() {
+        let shuffled = |seed| {
+            let mut map = EasyMmapBuilder::<u32>::new()
+                .capacity(100)
+                .readable()
+                .writable()
+                .build();
+            map.fill(|i| i as u32);
+            map.shuffle(seed);
+            map.get_data_as_slice().to_vec()
+        };
+
+        let first = shuffled(7);
+        assert_eq!(first, shuffled(7));
+        assert_ne!(first, (0..100).collect::<Vec<_>>());
+
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn running_checksum_after_writes() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(64)
+            .readable()
+            .writable()
+            .build();
+        let initial = map.running_checksum();
+
+        map[3] = 7;
+        map[10] += 5;
+        map.write_at(20, &[1, 2, 3]);
+        map[3] = 9;
+        let running = map.running_checksum();
+        assert_ne!(running, initial);
+
+        // A full rescan gives the same result
+        map.get_data_as_slice_mut();
+        assert_eq!(map.running_checksum(), running);
+
+        map[3] = 0;
+        map[10] = 0;
+        map.write_at(20, &[0, 0, 0]);
+        assert_eq!(map.running_checksum(), initial);
+    }
+
+    #[test]
+    fn get_many_mut_disjoint() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+
+        let [a, b] = map.get_many_mut([0, 4]).unwrap();
+        *a = 1;
+        *b = 2;
+        assert_eq!(map.get_data_as_slice(), &[1, 0, 0, 0, 2]);
+
+        assert!(map.get_many_mut([0, 0]).is_none());
+        assert!(map.get_many_mut([0, 5]).is_none());
+    }
+
+    #[test]
+    fn display_summary() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .readable()
+            .file(create_random_file())
+            .build();
+        assert_eq!(
+            map.to_string(),
+            "EasyMmap<u32>[10 elements, 40 bytes, file-backed]"
+        );
+    }
+
+    #[test]
+    fn read_write_adapters() {
+        use std::io::{Read, Write};
+
+        let mut map = EasyMmapBuilder::<u16>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .build();
+        map.writer().write_all(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert!(map.writer().write_all(&[0; 9]).is_err());
+
+        let mut bytes = Vec::new();
+        map.reader().read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, map.as_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_find_any_match() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(100_000)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32);
+
+        assert_eq!(map.par_find_any(|x| *x > 99998), Some(&99999));
+        assert_eq!(map.par_find_any(|x| *x > 100_000), None);
+    }
+
+    #[test]
+    fn from_raw_heap_view() {
+        let mut boxed = vec![1u32, 2, 3, 4].into_boxed_slice();
+        let mut map = unsafe { EasyMmap::from_raw(boxed.as_mut_ptr(), boxed.len()) };
+        assert_eq!(map.get_data_as_slice(), &[1, 2, 3, 4]);
+        map[0] = 10;
+        assert!(map.resize(8).is_err());
+        drop(map);
+        assert_eq!(&boxed[..], &[10, 2, 3, 4]);
+    }
+
+    #[test]
+    fn sync_on_write_schedules_writes() {
+        let file = create_random_file();
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .sync_on_write()
+            .build();
+
+        map.write_at(1, &[7, 8]);
+        map[0] = 5;
+        assert_eq!(map.sync_pending.get(), Some(0));
+        map.replace(3, 9);
+        assert!(map.sync_pending.get().is_none());
+        assert_eq!(map.get_data_as_slice(), &[5, 7, 8, 9]);
+    }
+
+    #[test]
+    fn align_to_simd_split() {
+        let map = EasyMmapBuilder::<u8>::new()
+            .capacity(100)
+            .readable()
+            .build();
+
+        let (prefix, middle, suffix) = map.align_to_simd::<u64>();
+        assert_eq!(prefix.len() + middle.len() * 8 + suffix.len(), map.len());
+        // The map is page-aligned, so only the suffix is left over
+        assert_eq!((prefix.len(), middle.len(), suffix.len()), (0, 12, 4));
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn from_par_iter_collect() {
+        let map = EasyMmap::from_par_iter((0..100_000u64).into_par_iter().map(|x| x * 2));
+        assert_eq!(map.len(), 100_000);
+        assert!(map.iter().enumerate().all(|(i, x)| *x == i as u64 * 2));
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn from_par_iter_empty() {
+        let map = EasyMmap::from_par_iter((0..0u64).into_par_iter());
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn read_only_file_writable_map() {
+        let (path, file) = create_random_file_with_path();
+        file.set_len(16).unwrap();
+
+        let result = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(fs::File::open(&path).unwrap())
+            .try_build();
+        assert!(matches!(result, Err(EasyMmapError::ProtModeMismatch)));
+    }
+
+    #[test]
+    fn read_only_file_read_only_map() {
+        let (path, file) = create_random_file_with_path();
+        file.write_all_at(&[1, 2, 3, 4], 0).unwrap();
+
+        let map = EasyMmapBuilder::<u8>::new()
+            .capacity(4)
+            .readable()
+            .file(fs::File::open(&path).unwrap())
+            .build();
+        assert_eq!(map.get_data_as_slice(), &[1, 2, 3, 4]);
+
+        let (path, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .with_header()
+            .build();
+        map.fill(|i| i as u32);
+        map.write_checksum().unwrap();
+        drop(map);
+
+        let map = EasyMmapBuilder::<u32>::new()
+            .readable()
+            .file(fs::File::open(&path).unwrap())
+            .with_header()
+            .capacity_from_file()
+            .verify_checksum()
+            .build();
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn map_memfd() {
+        let fd = unsafe { libc::memfd_create(c"easy_mmap".as_ptr(), 0) };
+        assert!(fd >= 0);
+        assert_eq!(unsafe { libc::ftruncate(fd, 16) }, 0);
+
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .fd(fd)
+            .build();
+        map.fill(|i| i as u32);
+
+        let other = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .fd(fd)
+            .build();
+        assert_eq!(other.get_data_as_slice(), &[0, 1, 2, 3]);
+
+        drop((map, other));
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn seal_lookup_table() {
+        let mut table = EasyMmapBuilder::<u32>::new()
+            .capacity(8)
+            .readable()
+            .writable()
+            .build()
+            .seal(|i| (i * i) as u32)
+            .unwrap();
+        assert_eq!(table[3], 9);
+        assert!(!table.options().contains(MapFlags::WRITABLE));
+
+        table[3] = 0;
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_sort_records_by_key() {
+        #[derive(Clone, Copy)]
+        struct Record {
+            key: u32,
+            value: u32,
+        }
+
+        let mut map = EasyMmapBuilder::<Record>::new()
+            .capacity(1000)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| Record {
+            key: (i as u32 * 7919) % 1000,
+            value: i as u32,
+        });
+
+        map.par_sort_by_key(|r| r.key);
+        assert!(map.iter().enumerate().all(|(i, r)| r.key == i as u32));
+        assert!(map.iter().all(|r| (r.value * 7919) % 1000 == r.key));
+    }
+
+    #[test]
+    fn replace_returns_old() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32 * 10);
+
+        assert_eq!(map.replace(2, 99), 20);
+        assert_eq!(map[2], 99);
+    }
+
+    #[test]
+    fn double_buffer_swap() {
+        let (path, front) = create_random_file_with_path();
+        let mut buffer = DoubleBuffer::<u32>::with_files(4, front, create_random_file())
+            .unwrap()
+            .flush_on_swap();
+
+        buffer.back_mut().fill(|i| i as u32 + 1);
+        assert_eq!(buffer.front().get_data_as_slice(), &[0, 0, 0, 0]);
+
+        buffer.swap().unwrap();
+        assert_eq!(buffer.front().get_data_as_slice(), &[1, 2, 3, 4]);
+
+        buffer.back_mut().write_at(0, &[9]);
+        buffer.swap().unwrap();
+        assert_eq!(buffer.front()[0], 9);
+        assert_eq!(fs::read(&path).unwrap()[..4], 9u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn compact_file_tail() {
+        let (path, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+        map.fill(|i| i as u32);
+
+        let kept = map.partition_in_place(|x| x % 2 == 0);
+        map.compact_file(kept).unwrap();
+        assert_eq!(map.len(), 5);
+        drop(map);
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let map = EasyMmapBuilder::<u32>::new()
+            .readable()
+            .file(file)
+            .capacity_from_file()
+            .build();
+        assert_eq!(map.get_data_as_slice(), &[0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn compact_file_to_zero() {
+        let (path, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+        map.fill(|i| i as u32);
+
+        let err = map.compact_file(0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 16);
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_histogram_buckets() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(100)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32);
+
+        assert_eq!(map.par_histogram(10, |v| *v as usize / 10), vec![10; 10]);
+    }
+
+    #[test]
+    fn write_through_bytes() {
+        let mut map = EasyMmapBuilder::<u16>::new()
+            .capacity(2)
+            .readable()
+            .writable()
+            .build();
+        map.as_bytes_mut()
+            .copy_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+
+        assert_eq!(map[0], u16::from_ne_bytes([0x01, 0x02]));
+        assert_eq!(map[1], u16::from_ne_bytes([0x03, 0x04]));
+    }
+
+    #[test]
+    fn push_past_capacity() {
+        let (_, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(2)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+
+        map.push(1).unwrap();
+        map.push(2).unwrap();
+        map.grow_to(3).unwrap();
+        assert_eq!(map.pushed_len(), 2);
+        for v in 3..=10 {
+            map.push(v).unwrap();
+        }
+
+        assert_eq!(map.pushed_len(), 10);
+        assert_eq!(
+            &map.get_data_as_slice()[..10],
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]
+        );
+    }
+
+    #[test]
+    fn page_checksums_locate_change() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+        let page_size = MemoryMap::granularity();
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(page_size * 4)
+            .readable()
+            .writable()
+            .build();
+        let before = map.page_checksums();
+        assert_eq!(before.len(), 4);
+
+        map[page_size * 2 + 10] = 1;
+        let after = map.page_checksums();
+        let changed = (0..4)
+            .filter(|&i| before[i] != after[i])
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec![2]);
+    }
+
+    #[test]
+    fn into_vec_round_trip() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32 * 2);
+
+        assert_eq!(map.into_vec(), vec![0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn watch_file_changes() {
+        use std::io::Write;
+
+        let (path, file) = create_random_file_with_path();
+        let map = EasyMmapBuilder::<u8>::new()
+            .capacity(4)
+            .readable()
+            .file(file)
+            .build();
+        let mut changes = map.watch_changes().unwrap();
+
+        fs::OpenOptions::new()
             .write(true)
-            .open(format!("/tmp/map{}", rand::random::<u64>()))
+            .open(&path)
             .unwrap()
+            .write_all(&[1, 2])
+            .unwrap();
+        assert_eq!(changes.next(), Some(()));
+        assert_eq!(map[1], 2);
     }
 
     #[test]
-    fn map_create() {
-        let map = &mut EasyMmapBuilder::<u32>::new()
-            .capacity(10)
-            .options(&[])
+    fn with_flush_persists() {
+        let (path, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(3)
+            .readable()
+            .writable()
+            .file(file)
             .build();
 
-        assert_eq!(map.len(), 10);
+        let sum = map
+            .with_flush(|map| {
+                map.write_at(0, &[1, 2, 3]);
+                map.iter().map(|x| *x as u32).sum::<u32>()
+            })
+            .unwrap();
+        assert_eq!(sum, 6);
+        assert!(!map.is_dirty());
+        assert_eq!(fs::read(&path).unwrap(), [1, 2, 3]);
     }
 
     #[test]
-    fn map_write_read() {
-        let map = &mut EasyMmapBuilder::<u32>::new()
+    #[cfg(feature = "rayon")]
+    fn fill_2d_coordinates() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(16)
+            .readable()
+            .writable()
+            .build();
+        map.fill_2d(4, |row, col| (row * 10 + col) as u32);
+
+        assert_eq!(map[0], 0);
+        assert_eq!(map[3], 3);
+        assert_eq!(map[4 + 2], 12);
+        assert_eq!(map[15], 33);
+    }
+
+    #[test]
+    fn validate_before_build() {
+        let overflow = EasyMmapBuilder::<u64>::new()
+            .capacity(usize::MAX / 4)
+            .readable()
+            .validate();
+        assert!(matches!(
+            overflow,
+            Err(EasyMmapError::CapacityOverflow(c)) if c == usize::MAX / 4
+        ));
+
+        let zero_sized = EasyMmapBuilder::<Zst>::new().capacity(1).validate();
+        assert!(matches!(zero_sized, Err(EasyMmapError::ZeroSizedType)));
+
+        let conflicting = EasyMmapBuilder::<u8>::new()
             .capacity(1)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .writable()
+            .add_option(MapOption::MapExecutable)
+            .validate();
+        assert!(matches!(conflicting, Err(EasyMmapError::WriteExecute)));
+
+        let valid = EasyMmapBuilder::<u8>::new()
+            .capacity(1)
+            .readable()
+            .validate();
+        assert!(valid.is_ok());
+    }
+
+    #[test]
+    fn sealed_memfd_map() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .memfd("easy_mmap", true)
+            .build();
+        map.fill(|i| i as u32 + 1);
+        assert_eq!(map.get_data_as_slice(), &[1, 2, 3, 4]);
+
+        assert!(map.resize(8).is_err());
+        assert!(map.resize(2).is_err());
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn copy_from_file_map() {
+        let mut source = EasyMmapBuilder::<u32>::new()
+            .capacity(6)
+            .readable()
+            .writable()
+            .file(create_random_file())
+            .build();
+        source.fill(|i| i as u32 * 3);
+
+        let mut copy = EasyMmapBuilder::<u32>::new()
+            .capacity(6)
+            .readable()
+            .writable()
+            .build();
+        copy.copy_from_map(&source);
+        assert_eq!(copy.get_data_as_slice(), source.get_data_as_slice());
+    }
+
+    #[test]
+    fn filter_to_new_file() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(100)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32);
+
+        let (path, out) = create_random_file_with_path();
+        let evens = map.filter_to_file(|x| x % 2 == 0, out).unwrap();
+        assert_eq!(evens.len(), 50);
+        assert!(evens.iter().enumerate().all(|(i, x)| *x == i as u32 * 2));
+        drop(evens);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 200);
+    }
+
+    #[test]
+    fn filter_to_file_no_match() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
+            .readable()
+            .writable()
+            .build();
+
+        let (path, out) = create_random_file_with_path();
+        let none = map.filter_to_file(|x| *x > 0, out).unwrap();
+        assert_eq!(none.len(), 0);
+        drop(none);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn scan_with_prefetch_sum() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(10_000)
+            .readable()
+            .writable()
+            .file(create_random_file())
+            .build();
+        map.fill(|i| i as u64);
+
+        let mut sum = 0;
+        map.scan_with_prefetch(1000, |values| sum += values.iter().sum::<u64>())
+            .unwrap();
+        assert_eq!(sum, map.iter().sum::<u64>());
+    }
+
+    #[test]
+    fn index_inclusive_range() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32);
+
+        assert_eq!(&map[1..=3], &[1, 2, 3]);
+        map[..=1].copy_from_slice(&[7, 8]);
+        assert_eq!(&map[..=4], &[7, 8, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Range 0..=5 is out of bounds")]
+    fn index_inclusive_range_past_end() {
+        let map = EasyMmapBuilder::<u32>::new().capacity(5).readable().build();
+        let len = map.len();
+        let _ = &map[..=len];
+    }
+
+    #[test]
+    fn element_at_byte_offset() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32 * 100);
+
+        assert_eq!(*map.at_byte_offset(8), 200);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn fill_parallel_with_progress() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(1050)
+            .readable()
+            .writable()
+            .build();
+        let chunks = AtomicUsize::new(0);
+        map.fill_parallel_progress(
+            100,
+            |i| i as u32,
+            |_| {
+                chunks.fetch_add(1, Ordering::Relaxed);
+            },
+        );
+
+        assert_eq!(chunks.load(Ordering::Relaxed), 11);
+        assert!(map.iter().enumerate().all(|(i, x)| *x == i as u32));
+    }
+
+    #[test]
+    fn exclusive_file_lock() {
+        let (path, file) = create_random_file_with_path();
+        let map = EasyMmapBuilder::<u8>::new()
+            .capacity(4)
+            .readable()
+            .writable()
+            .file(file)
+            .build();
+        map.lock_file_exclusive().unwrap();
+
+        let other = fs::File::open(&path).unwrap();
+        assert!(flock(&other, libc::LOCK_EX | libc::LOCK_NB).is_err());
+
+        map.unlock_file().unwrap();
+        flock(&other, libc::LOCK_EX | libc::LOCK_NB).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_for_each_indexed_writes() {
+        let mut map = EasyMmapBuilder::<usize>::new()
+            .capacity(1000)
+            .readable()
+            .writable()
+            .build();
+        map.par_for_each_indexed(|i, v| *v = i);
+
+        assert_eq!(map.get_data_as_slice(), (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn freeze_shared_across_threads() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(100)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|i| i as u32);
+        let frozen = map.freeze();
+
+        let sums = std::thread::scope(|s| {
+            let handles = (0..2)
+                .map(|_| s.spawn(|| frozen.iter().sum::<u32>()))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(sums, vec![4950, 4950]);
+        assert_eq!(frozen[10], 10);
+        assert_eq!(frozen.get(100), None);
+    }
+
+    #[test]
+    fn file_offset_of_region() {
+        let page_size = MemoryMap::granularity();
+        let file = create_random_file();
+        file.set_len((page_size * 2) as u64).unwrap();
+
+        let map = super::map_region::<u32>(file, page_size / 4, 8).unwrap();
+        assert_eq!(map.file_offset_of(0), Some(page_size as u64));
+        assert_eq!(map.file_offset_of(2), Some(page_size as u64 + 8));
+        assert_eq!(map.file_offset_of(8), None);
+
+        let anonymous = EasyMmapBuilder::<u32>::new().capacity(1).readable().build();
+        assert_eq!(anonymous.file_offset_of(0), None);
+    }
+
+    #[test]
+    fn snapshot_into_destination() {
+        let mut source = EasyMmapBuilder::<u32>::new()
+            .capacity(3)
+            .readable()
+            .writable()
+            .build();
+        let mut dest = EasyMmapBuilder::<u32>::new()
+            .capacity(3)
+            .readable()
+            .writable()
+            .build();
+
+        source.write_at(0, &[1, 2, 3]);
+        source.snapshot_into(&mut dest).unwrap();
+        assert_eq!(dest.get_data_as_slice(), &[1, 2, 3]);
+
+        source[1] = 20;
+        source.snapshot_into(&mut dest).unwrap();
+        assert_eq!(dest.get_data_as_slice(), &[1, 20, 3]);
+
+        let mut short = EasyMmapBuilder::<u32>::new()
+            .capacity(2)
+            .readable()
+            .writable()
+            .build();
+        assert_eq!(
+            source.snapshot_into(&mut short),
+            Err(LenMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn huge_pages_with_fallback() {
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity(4 << 20)
+            .readable()
+            .writable()
+            .huge_pages_or_fallback()
+            .build();
+        map[0] = 1;
+        assert_eq!(map[0], 1);
+
+        let normal = EasyMmapBuilder::<u8>::new().capacity(1).readable().build();
+        assert!(!normal.uses_huge_pages());
+    }
+
+    #[test]
+    fn huge_pages_partial_capacity() {
+        let mut map = EasyMmapBuilder::<u8>::new()
+            .capacity((2 << 20) + 1)
+            .readable()
+            .writable()
+            .huge_pages_or_fallback()
+            .build();
+        if map.uses_huge_pages() {
+            assert_eq!(map.region().1 % huge_page_size(), 0);
+        }
+        map[2 << 20] = 1;
+
+        let map = map.seal(|i| i as u8).unwrap();
+        assert_eq!(map[2 << 20], 0);
+    }
+
+    #[test]
+    fn prefix_sum_running_total() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
+            .build();
+        map.fill(|_| 1);
+        map.prefix_sum();
+        assert_eq!(map.get_data_as_slice(), &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn concat_two_files() {
+        let (first, _) = create_random_file_with_path();
+        let (second, _) = create_random_file_with_path();
+        fs::write(&first, [1u32, 2, 3, 4].map(u32::to_ne_bytes).concat()).unwrap();
+        fs::write(&second, [5u32, 6, 7, 8].map(u32::to_ne_bytes).concat()).unwrap();
+
+        let open = |path| fs::File::open(path).unwrap();
+        let map = concat_files::<u32>(vec![open(&first), open(&second)]).unwrap();
+        assert_eq!(map.get_data_as_slice(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(!map.options().contains(MapFlags::WRITABLE));
+    }
+
+    #[test]
+    fn write_read_barrier() {
+        use std::sync::atomic::Ordering;
+
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(3)
+            .readable()
+            .writable()
+            .auto_sharing()
+            .build();
+
+        unsafe {
+            let pid = libc::fork();
+            assert!(pid >= 0);
+            if pid == 0 {
+                map[1] = 7;
+                map[2] = 9;
+                map.write_barrier();
+                map.as_atomic()[0].store(1, Ordering::Relaxed);
+                libc::_exit(0);
+            }
+
+            while map.as_atomic()[0].load(Ordering::Relaxed) == 0 {
+                std::hint::spin_loop();
+            }
+            map.read_barrier();
+            assert_eq!((map[1], map[2]), (7, 9));
+
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_tally() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(100)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| i as u32);
+
+        let is_even = |v: &u32| v % 2 == 0;
+        let above_50 = |v: &u32| *v > 50;
+        assert_eq!(map.par_tally([&is_even, &above_50]), [50, 49]);
+    }
+
+    #[test]
+    fn mismatch_policy() {
+        let build = |capacity, policy| {
+            let file = create_random_file();
+            file.set_len(10 * 4).unwrap();
+            EasyMmapBuilder::<u32>::new()
+                .capacity(capacity)
+                .readable()
+                .writable()
+                .file(file)
+                .mismatch_policy(policy)
+                .try_build()
+        };
+
+        assert!(matches!(
+            build(5, MismatchPolicy::Error),
+            Err(EasyMmapError::FileSizeMismatch {
+                expected: 20,
+                found: 40
+            })
+        ));
+        assert_eq!(build(10, MismatchPolicy::Error).unwrap().len(), 10);
+
+        let map = build(5, MismatchPolicy::Truncate).unwrap();
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.backing_file().unwrap().metadata().unwrap().len(), 20);
+        assert!(build(20, MismatchPolicy::Truncate).is_err());
+
+        let map = build(20, MismatchPolicy::Extend).unwrap();
+        assert_eq!(map.len(), 20);
+        assert_eq!(map.backing_file().unwrap().metadata().unwrap().len(), 80);
+        assert!(build(5, MismatchPolicy::Extend).is_err());
+
+        assert_eq!(build(5, MismatchPolicy::UseFileSize).unwrap().len(), 10);
+        assert_eq!(build(20, MismatchPolicy::UseFileSize).unwrap().len(), 10);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_min_max() {
+        let mut map = EasyMmapBuilder::<i64>::new()
+            .capacity(100000)
+            .readable()
+            .writable()
+            .build();
+
+        map.fill(|i| (i as i64 * 7919) % 100003 - 50000);
+
+        let min = *map.iter().min().unwrap();
+        let max = *map.iter().max().unwrap();
+        assert_eq!(map.par_min_max(), Some((min, max)));
+
+        map.truncate(0);
+        assert_eq!(map.par_min_max(), None);
+    }
+
+    #[test]
+    fn fill_channels() {
+        let mut map = EasyMmapBuilder::<i16>::new()
+            .capacity(8)
+            .readable()
+            .writable()
+            .build();
+        map.fill_channels(2, |frame, channel| {
+            frame as i16 * if channel == 0 { 1 } else { -1 }
+        });
+
+        assert_eq!(map.get_data_as_slice(), &[0, 0, 1, -1, 2, -2, 3, -3]);
+    }
+
+    #[test]
+    fn repair_file() {
+        let file = create_random_file();
+        file.set_len(3 * 8 + 5).unwrap();
+
+        assert_eq!(super::repair_file::<u64>(&file).unwrap(), 3);
+        assert_eq!(file.metadata().unwrap().len(), 24);
+        assert_eq!(super::repair_file::<u64>(&file).unwrap(), 3);
+    }
+
+    #[test]
+    #[ignore = "requires a NUMA-capable kernel"]
+    fn numa_touch() {
+        let map = EasyMmapBuilder::<u64>::new()
+            .capacity(1 << 24)
+            .readable()
+            .writable()
+            .build();
+
+        map.numa_touch(|_| 0).unwrap();
+    }
+
+    #[test]
+    fn grow_in_place() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(1000)
+            .readable()
+            .writable()
+            .file(create_random_file())
+            .build();
+        map.fill(|i| i as u64);
+
+        map.grow_in_place(100000).unwrap();
+        assert_eq!(map.len(), 100000);
+        assert_eq!(
+            map.backing_file().unwrap().metadata().unwrap().len(),
+            800000
+        );
+        assert!((0..1000).all(|i| map[i] == i as u64));
+        assert!((1000..100000).all(|i| map[i] == 0));
+
+        map[99999] = 7;
+        assert_eq!(map[99999], 7);
+    }
+
+    #[test]
+    fn checkpoint_rollback() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
+            .readable()
+            .writable()
             .build();
+        map.fill(|i| i as u32);
+
+        let checkpoint = map.checkpoint();
+        map.fill(|_| 9);
+        map[2] = 42;
+
+        map.rollback(&checkpoint);
+        assert_eq!(map.get_data_as_slice(), &[0, 1, 2, 3, 4]);
+    }
 
-        map[0] = 1;
+    #[test]
+    fn from_reader() {
+        let values = [1u32, 2, 0xDEADBEEF, 4];
+        let bytes = values
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect::<Vec<_>>();
 
-        assert_eq!(map[0], 1);
+        let map = super::from_reader::<u32, _>(&bytes[..]).unwrap();
+        assert_eq!(map.get_data_as_slice(), &values);
+
+        let err = super::from_reader::<u32, _>(&bytes[..7]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let map = super::from_reader::<u32, _>(io::empty()).unwrap();
+        assert_eq!(map.len(), 0);
     }
 
     #[test]
-    fn map_iter() {
-        let map = &mut EasyMmapBuilder::<u32>::new()
+    fn swap_with() {
+        let mut a = EasyMmapBuilder::<u32>::new()
+            .capacity(3)
+            .readable()
+            .writable()
+            .build();
+        let mut b = EasyMmapBuilder::<u32>::new()
             .capacity(5)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .readable()
+            .writable()
+            .file(create_random_file())
             .build();
+        a.fill(|i| i as u32);
+        b.fill(|i| 10 + i as u32);
 
-        for i in 0..5 {
-            map[i] = i as u32;
-        }
-
-        assert_eq!(
-            map.iter().map(|x| *x).collect::<Vec<_>>(),
-            vec![0, 1, 2, 3, 4]
-        );
+        a.swap_with(&mut b);
+        assert_eq!(a.get_data_as_slice(), &[10, 11, 12, 13, 14]);
+        assert_eq!(b.get_data_as_slice(), &[0, 1, 2]);
+        assert!(a.backing_file().is_ok());
+        assert!(b.backing_file().is_err());
     }
 
     #[test]
-    #[should_panic]
-    fn map_oob_write() {
-        let map = &mut EasyMmapBuilder::<u32>::new()
-            .capacity(1)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+    #[cfg(feature = "hll")]
+    fn approx_cardinality() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(100000)
+            .readable()
+            .writable()
             .build();
+        map.fill(|i| (i as u64 * 7919) % 1000);
 
-        map[1] = 1;
+        let estimate = map.approx_cardinality();
+        assert!((950..=1050).contains(&estimate), "estimate {}", estimate);
     }
 
     #[test]
-    #[should_panic]
-    fn map_oob_read() {
-        let map = &mut EasyMmapBuilder::<u32>::new()
-            .capacity(1)
+    fn normalize_duplicated_options() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(5)
             .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .readable()
+            .writable()
+            .add_option(MapOption::MapReadable)
+            .file(create_random_file())
             .build();
 
-        map[1];
+        // Readable, writable, the file descriptor and the sharing flags
+        assert_eq!(map._options.len(), 4);
+        map[4] = 7;
+        assert_eq!(map[4], 7);
+        map.resize(10).unwrap();
+        assert_eq!(map[4], 7);
     }
 
     #[test]
-    fn map_create_file() {
-        let file = create_random_file();
-
-        let map = &mut EasyMmapBuilder::<u32>::new()
-            .file(file)
+    fn array_ref() {
+        let mut map = EasyMmapBuilder::<u32>::new()
             .capacity(10)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .readable()
+            .writable()
             .build();
+        map.fill(|i| i as u32);
 
-        assert_eq!(map.len(), 10);
-
-        // Write to file
-        map[0] = 1;
-        assert_eq!(map[0], 1);
+        assert_eq!(map.array_ref::<4>(2), Some(&[2, 3, 4, 5]));
+        assert_eq!(map.array_ref::<4>(6), Some(&[6, 7, 8, 9]));
+        assert_eq!(map.array_ref::<4>(7), None);
     }
 
     #[test]
-    fn test_large_size() {
-        let map = &mut EasyMmapBuilder::new()
-            .capacity(65535)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+    fn persist_as() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .readable()
+            .writable()
             .build();
+        map.fill(|i| i as u32);
 
-        // Populate map
-        for i in 0..65535 {
-            map[i] = i as u64;
-        }
+        let path = format!("/tmp/map{}", rand::random::<u64>());
+        let mut map = map.persist_as(&path).unwrap();
+        map[3] = 42;
+        map.flush().unwrap();
 
-        // Check if map is populated
-        for i in 0..65535 {
-            assert_eq!(map[i], i as u64);
-        }
+        let bytes = fs::read(&path).unwrap();
+        let values = bytes
+            .chunks(4)
+            .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(values, [0, 1, 2, 42]);
+        fs::remove_file(path).unwrap();
     }
 
     #[test]
-    fn test_struct() {
-        #[derive(Clone, Copy)]
-        struct TestStruct {
-            v1: i64,
-            v2: bool,
-        }
-
-        let length = 100000;
-
-        let file = create_random_file();
-
-        let map = &mut EasyMmapBuilder::new()
-            .capacity(length)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
-            .file(file)
+    #[cfg(feature = "rayon")]
+    fn map_with_neighbors() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(6)
+            .readable()
+            .writable()
             .build();
+        map.fill(|i| i as u32 * 3);
 
-        for i in 0..length {
-            map[i] = TestStruct {
-                v1: i as i64,
-                v2: i % 2 == 0,
-            };
-        }
-
-        for i in 0..length {
-            let s = map[i];
-            assert_eq!(s.v1, i as i64);
-            assert_eq!(s.v2, i % 2 == 0);
-        }
+        let average = map.map_with_neighbors(1, |w| w.iter().sum::<u32>() / w.len() as u32);
+        assert_eq!(average.get_data_as_slice(), &[1, 3, 6, 9, 12, 13]);
     }
 
     #[test]
-    fn test_iter() {
-        let mut map = EasyMmapBuilder::<i32>::new()
-            .capacity(5)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+    fn verify_checksum() {
+        let (path, file) = create_random_file_with_path();
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(16)
+            .readable()
+            .writable()
+            .file(file)
+            .with_header()
             .build();
+        map.fill(|i| i as u32);
+        map.write_checksum().unwrap();
+        drop(map);
 
-        for i in 0..5 {
-            map[i] = i as i32;
-        }
+        let open = || {
+            EasyMmapBuilder::<u32>::new()
+                .capacity(16)
+                .readable()
+                .file(
+                    fs::OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .open(&path)
+                        .unwrap(),
+                )
+                .with_header()
+                .verify_checksum()
+                .try_build()
+        };
+        assert_eq!(open().unwrap()[15], 15);
 
-        for (i, x) in map.iter().enumerate() {
-            assert_eq!(i as i32, *x);
-        }
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[0xFF], (HEADER_LEN + 5) as u64).unwrap();
+        assert!(matches!(
+            open(),
+            Err(EasyMmapError::ChecksumMismatch { .. })
+        ));
     }
 
     #[test]
-    fn test_iter_mut() {
+    fn clamp_all() {
         let mut map = EasyMmapBuilder::<i32>::new()
-            .capacity(5)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .capacity(11)
+            .readable()
+            .writable()
             .build();
+        map.fill(|i| i as i32 - 5);
 
-        for (i, x) in map.iter_mut().enumerate() {
-            *x = i as i32;
-        }
-
-        for (i, x) in map.iter().enumerate() {
-            assert_eq!(i as i32, *x);
-        }
+        map.clamp_all(-2, 2);
+        assert_eq!(
+            map.get_data_as_slice(),
+            &[-2, -2, -2, -2, -1, 0, 1, 2, 2, 2, 2]
+        );
     }
 
     #[test]
-    fn test_complex_iterator() {
+    fn chunks_exact() {
         let mut map = EasyMmapBuilder::<u32>::new()
-            .capacity(5)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+            .capacity(10)
+            .readable()
+            .writable()
             .build();
+        map.fill(|i| i as u32);
 
-        map.iter_mut()
-            .enumerate()
-            .for_each(|(idx, x)| *x = idx as u32);
-
-        let v = map
-            .iter()
-            .map(|x| *x * 3)
-            .filter(|x| x % 2 == 0)
-            .collect::<Vec<u32>>();
-
-        map.iter_mut().zip(v).for_each(|(x, y)| *x = y);
-
+        let chunks = map.chunks_exact(3);
+        assert_eq!(chunks.remainder(), &[9]);
         assert_eq!(
-            map.iter().map(|x| *x).collect::<Vec<_>>(),
-            vec![0, 6, 12, 3, 4]
+            chunks.collect::<Vec<_>>(),
+            [&[0, 1, 2][..], &[3, 4, 5], &[6, 7, 8]]
         );
     }
 
     #[test]
-    fn get_data_slice() {
-        let mut map = EasyMmapBuilder::<u32>::new()
-            .capacity(5)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
+    #[cfg(feature = "rayon")]
+    fn par_write_records() {
+        let mut map = EasyMmapBuilder::<u64>::new()
+            .capacity(100000)
+            .readable()
+            .writable()
+            .file(create_random_file())
             .build();
 
-        map.iter_mut()
-            .enumerate()
-            .for_each(|(idx, x)| *x = idx as u32);
+        map.par_write_records(
+            (0..100000usize)
+                .into_par_iter()
+                .rev()
+                .map(|i| (i, i as u64)),
+        );
+        assert!(map.iter().enumerate().all(|(i, v)| *v == i as u64));
+    }
 
-        let slice = map.get_data_as_slice();
+    #[test]
+    fn at_address() {
+        let addr = 0x7e00_0000_0000;
+        let build = || {
+            EasyMmapBuilder::<u64>::new()
+                .capacity(16)
+                .readable()
+                .writable()
+                .at_address(addr)
+                .try_build()
+        };
 
-        assert_eq!(slice.len(), 5);
-        assert_eq!(slice[0], map[0]);
-        assert_eq!(slice[1], map[1]);
-        assert_eq!(slice[2], map[2]);
-        assert_eq!(slice[3], map[3]);
-        assert_eq!(slice[4], map[4]);
+        match build() {
+            Ok(mut map) => {
+                assert_eq!(map.as_ptr() as usize, addr);
+                map[15] = 7;
+                // The existing map is never replaced
+                assert!(matches!(
+                    build(),
+                    Err(EasyMmapError::AddressUnavailable(a)) if a == addr
+                ));
+                assert_eq!(map[15], 7);
+            }
+            Err(EasyMmapError::AddressUnavailable(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
 
-        let slice = map.get_data_as_slice_mut();
-        assert_eq!(slice.len(), 5);
-        slice[0] = 10;
+    #[test]
+    fn diff() {
+        let build = || {
+            let mut map = EasyMmapBuilder::<u32>::new()
+                .capacity(5)
+                .readable()
+                .writable()
+                .build();
+            map.fill(|i| i as u32);
+            map
+        };
+        let a = build();
+        let mut b = build();
+        b[1] = 10;
+        b[3] = 30;
 
-        assert_eq!(map[0], 10);
+        assert_eq!(a.diff(&b), [(1, 1, 10), (3, 3, 30)]);
+        assert!(a.diff(&a).is_empty());
     }
 
     #[test]
-    fn easier_builder() {
-        let mut map = EasyMmapBuilder::<i32>::new()
-            .capacity(1)
+    fn recycle() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
             .readable()
             .writable()
             .build();
+        for i in 1..=3 {
+            map.push(i).unwrap();
+        }
 
-        map[0] = 1;
-        assert_eq!(map[0], 1);
+        map.recycle();
+        assert_eq!(map.pushed_len(), 0);
+        assert!(!map.is_dirty());
+        assert_eq!(map.get_data_as_slice(), &[0, 0, 0, 0]);
+        map.push(9).unwrap();
+        assert_eq!(map[0], 9);
     }
 
     #[test]
-    fn fill_constant() {
-        let mut map = EasyMmapBuilder::<i32>::new()
-            .capacity(5)
+    fn seal_after() {
+        let map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
+            .seal_after(|i| i as u32 * 2)
+            .unwrap();
+
+        assert_eq!(map.get_data_as_slice(), &[0, 2, 4, 6]);
+        assert!(!map.options().contains(MapFlags::WRITABLE));
+    }
+
+    #[test]
+    fn into_chunks() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(10)
             .readable()
             .writable()
             .build();
+        map.fill(|i| i as u32);
+        let original = map.checkpoint();
 
-        map.fill(|_| 1);
-        assert_eq!(map.get_data_as_slice(), vec![1, 1, 1, 1, 1]);
+        let chunks = map.into_chunks(3);
+        assert_eq!(
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+            [3, 3, 4]
+        );
+        let joined = chunks
+            .iter()
+            .flat_map(|c| c.iter().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(joined, original);
     }
 
     #[test]
-    fn fill_large() {
-        let mut map = EasyMmapBuilder::<i32>::new()
-            .capacity(100000)
+    fn fill_from_reader() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(4)
             .readable()
             .writable()
+            .file(create_random_file())
             .build();
 
-        map.fill(|i| i as i32);
-        assert_eq!(map.get_data_as_slice(), (0..100000).collect::<Vec<_>>());
+        let bytes = [5u32, 6, 7]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(map.fill_from_reader(&bytes[..]).unwrap(), 3);
+        assert_eq!(map.get_data_as_slice(), &[5, 6, 7, 0]);
+
+        let bytes = [1u32; 6]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(map.fill_from_reader(&bytes[..]).unwrap(), 4);
+        assert_eq!(map.get_data_as_slice(), &[1, 1, 1, 1]);
     }
 
+    // Bulk mutations must check for writability in release builds too, or they fault on the sealed region
     #[test]
-    fn open_written_file() {
-        let values = vec![1, 2, 3, 4, 5, 10, 20, 50];
-
-        // Write to random file
-        let filename = format!("/tmp/file{}", rand::random::<i32>());
-        fs::write(&filename, &values).expect("Failed to write values to file");
+    #[should_panic(expected = "not writable")]
+    fn reverse_sealed() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(8)
+            .seal_after(|i| i as u32)
+            .unwrap();
 
-        let file = fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(false)
-            .open(&filename)
-            .expect("Failed to open file");
+        map.reverse();
+    }
 
-        // Now read the contents into the mmap
-        let map = EasyMmapBuilder::<u8>::new()
-            .capacity(values.len())
-            .writable()
-            .readable()
-            .file(file)
-            .build();
+    #[test]
+    #[should_panic(expected = "not writable")]
+    fn recycle_sealed() {
+        let mut map = EasyMmapBuilder::<u32>::new()
+            .capacity(8)
+            .seal_after(|i| i as u32)
+            .unwrap();
 
-        assert_eq!(map.get_data_as_slice(), values);
+        map.recycle();
     }
 
     #[test]
-    fn parallel_iterators() {
-        let mut map = EasyMmapBuilder::<i32>::new()
-            .capacity(5)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
-            .build();
+    fn grow_in_place_at_address() {
+        let addr = 0x7d00_0000_0000;
+        let build = |addr, file| {
+            EasyMmapBuilder::<u64>::new()
+                .capacity(512)
+                .readable()
+                .writable()
+                .file(file)
+                .at_address(addr)
+                .try_build()
+        };
+        let Ok(mut map) = build(addr, create_random_file()) else {
+            return;
+        };
+        map.fill(|i| i as u64);
 
-        map.fill(|i| i as i32);
+        // The map is extended where it is, keeping its address
+        map.grow_in_place(1024).unwrap();
+        assert_eq!(map.as_ptr() as usize, addr);
+        assert_eq!(map.len(), 1024);
+        assert!((0..512).all(|i| map[i] == i as u64));
 
+        // With the following page taken, the map can't grow without moving, and is left as it was
+        let Ok(_blocker) = build(addr + 1024 * 8, create_random_file()) else {
+            return;
+        };
+        assert!(map.grow_in_place(100000).is_err());
+        assert_eq!(map.len(), 1024);
         assert_eq!(
-            map.par_iter().map(|x| *x).collect::<Vec<_>>(),
-            (0..5).collect::<Vec<_>>()
+            map.backing_file().unwrap().metadata().unwrap().len(),
+            1024 * 8
         );
+        assert!((0..512).all(|i| map[i] == i as u64));
+        map[1023] = 7;
+        assert_eq!(map[1023], 7);
     }
 
     #[test]
-    fn parallel_iterators_mut() {
-        let mut map = EasyMmapBuilder::<i32>::new()
-            .capacity(5)
-            .options(&[MapOption::MapReadable, MapOption::MapWritable])
-            .build();
+    fn empty_maps() {
+        let empty = || {
+            EasyMmapBuilder::<u32>::new()
+                .capacity(0)
+                .readable()
+                .writable()
+                .build()
+        };
+        assert_eq!(empty().concat(&empty()).len(), 0);
 
-        map.fill(|i| i as i32);
+        let path = format!("/tmp/map{}", rand::random::<u64>());
+        let map = empty().persist_as(&path).unwrap();
+        assert_eq!(map.len(), 0);
+        drop(map);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
 
-        map.par_iter_mut().for_each(|x| *x += 1);
+        let map = EasyMmapBuilder::<u32>::new().load_file_copy(&path).unwrap();
+        assert_eq!(map.len(), 0);
+        fs::remove_file(path).unwrap();
 
-        assert_eq!(
-            map.par_iter().map(|x| *x).collect::<Vec<_>>(),
-            (1..6).collect::<Vec<_>>()
-        );
+        let files = vec![create_random_file(), create_random_file()];
+        let map = super::concat_files::<u32>(files).unwrap();
+        assert_eq!(map.len(), 0);
+
+        #[cfg(feature = "rayon")]
+        assert_eq!(empty().map_with_neighbors(1, |w| w[0]).len(), 0);
     }
 }